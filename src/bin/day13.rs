@@ -1,14 +1,30 @@
+use aoc21::image::{self, Bitmap};
+use aoc21::ocr;
 use nom::branch::alt;
 use nom::bytes::complete::tag;
 use nom::character::complete::{i32 as read_i32, newline};
+use nom::combinator::{map, opt, value};
 use nom::multi::{many_till, separated_list1};
+use nom::sequence::{preceded, separated_pair};
 use nom::IResult;
+use std::fmt;
 use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
 
 #[derive(Debug, PartialEq, Clone, Copy)]
 enum Fold {
     X(i32),
     Y(i32),
+    // Along x=y
+    Diagonal,
+    // Along x=-y+c
+    AntiDiagonal(i32),
+    // Half turn of the whole paper around a point, which keeps the origin on
+    // the paper so the point decides where the paper lands
+    Rotate(i32, i32),
 }
 
 /********************* Read input *********************/
@@ -23,14 +39,42 @@ fn read_dot(input: &str) -> IResult<&str, (i32, i32)> {
 
 fn read_fold(input: &str) -> nom::IResult<&str, Fold> {
     let (input, _) = tag("fold along ")(input)?;
-    let (input, dir) = alt((tag("x="), tag("y=")))(input)?;
-    let (input, line) = read_i32(input)?;
+    alt((
+        value(Fold::Diagonal, tag("x=y")),
+        map(preceded(tag("x=-y"), opt(read_i32)), |c| {
+            Fold::AntiDiagonal(c.unwrap_or(0))
+        }),
+        map(preceded(tag("x="), read_i32), Fold::X),
+        map(preceded(tag("y="), read_i32), Fold::Y),
+        map(
+            preceded(tag("point="), separated_pair(read_i32, tag(","), read_i32)),
+            |(x, y)| Fold::Rotate(x, y),
+        ),
+    ))(input)
+}
 
-    match dir {
-        "x=" => Ok((input, Fold::X(line))),
-        "y=" => Ok((input, Fold::Y(line))),
-        _ => panic!("Can't happen"),
-    }
+#[test]
+fn test_read_fold() {
+    assert_eq!(read_fold("fold along x=5"), Ok(("", Fold::X(5))));
+    assert_eq!(read_fold("fold along y=7"), Ok(("", Fold::Y(7))));
+    assert_eq!(read_fold("fold along x=y"), Ok(("", Fold::Diagonal)));
+    assert_eq!(
+        read_fold("fold along x=-y+9"),
+        Ok(("", Fold::AntiDiagonal(9)))
+    );
+    assert_eq!(
+        read_fold("fold along x=-y-2"),
+        Ok(("", Fold::AntiDiagonal(-2)))
+    );
+    assert_eq!(
+        read_fold("fold along x=-y"),
+        Ok(("", Fold::AntiDiagonal(0)))
+    );
+    assert_eq!(
+        read_fold("fold along point=3,4"),
+        Ok(("", Fold::Rotate(3, 4)))
+    );
+    assert!(read_fold("fold along z=3").is_err());
 }
 
 type Board = Vec<(i32, i32)>;
@@ -42,75 +86,983 @@ fn read(input: &str) -> nom::IResult<&str, (Board, Vec<Fold>)> {
 }
 
 /********************* Actual work *********************/
+type Dot = (i32, i32);
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+struct Bounds {
+    width: i32,
+    height: i32,
+}
+
+impl Bounds {
+    // The paper holds every dot, and puzzle folds halve the paper, so the
+    // first fold along an axis also tells us how long that axis is. Once the
+    // paper has been turned by any other kind of fold that no longer holds.
+    fn new(dots: &[Dot], folds: &[Fold]) -> Self {
+        let mut width = dots.iter().map(|&(x, _)| x + 1).max().unwrap_or(0);
+        let mut height = dots.iter().map(|&(_, y)| y + 1).max().unwrap_or(0);
+        let mut axis_folds = folds
+            .iter()
+            .take_while(|fold| matches!(fold, Fold::X(_) | Fold::Y(_)));
+        if let Some(f) = axis_folds.clone().find_map(|fold| match fold {
+            Fold::X(f) => Some(f),
+            _ => None,
+        }) {
+            width = width.max(2 * f + 1);
+        }
+        if let Some(f) = axis_folds.find_map(|fold| match fold {
+            Fold::Y(f) => Some(f),
+            _ => None,
+        }) {
+            height = height.max(2 * f + 1);
+        }
+        Self { width, height }
+    }
+
+    // Bounds before the first fold and after each one
+    fn history(&self, folds: &[Fold]) -> Vec<Bounds> {
+        let mut history = vec![*self];
+        for &fold in folds {
+            let (bounds, _) = history[history.len() - 1].fold(fold);
+            history.push(bounds);
+        }
+        history
+    }
+
+    fn cells(&self) -> usize {
+        self.width as usize * self.height as usize
+    }
+
+    fn contains(&self, (x, y): Dot) -> bool {
+        x >= 0 && y >= 0 && x < self.width && y < self.height
+    }
+
+    // Returns the folded bounds and the offset that moves dots into them
+    fn fold(&self, fold: Fold) -> (Bounds, Dot) {
+        let (kept, moved) = fold.sides(*self);
+        let moved = moved.map(|rect| {
+            let (a, b) = (fold.reflect(rect.lo), fold.reflect(rect.hi));
+            Rect {
+                lo: (a.0.min(b.0), a.1.min(b.1)),
+                hi: (a.0.max(b.0), a.1.max(b.1)),
+            }
+        });
+        let covered = match (kept, moved) {
+            (Some(a), Some(b)) => Some(Rect {
+                lo: (a.lo.0.min(b.lo.0), a.lo.1.min(b.lo.1)),
+                hi: (a.hi.0.max(b.hi.0), a.hi.1.max(b.hi.1)),
+            }),
+            (a, b) => a.or(b),
+        };
+        let covered = match fold {
+            Fold::Rotate(_, _) => covered.map(|Rect { lo, hi }| Rect {
+                lo: (lo.0.min(0), lo.1.min(0)),
+                hi: (hi.0.max(0), hi.1.max(0)),
+            }),
+            _ => covered,
+        };
+
+        match covered {
+            Some(Rect { lo, hi }) => {
+                let width = hi.0 - lo.0 + 1;
+                let height = hi.1 - lo.1 + 1;
+                (Bounds { width, height }, (-lo.0, -lo.1))
+            }
+            None => (
+                Bounds {
+                    width: 0,
+                    height: 0,
+                },
+                (0, 0),
+            ),
+        }
+    }
+}
+
+// Inclusive box of cells
+#[derive(Debug, PartialEq, Clone, Copy)]
+struct Rect {
+    lo: Dot,
+    hi: Dot,
+}
+
+impl Rect {
+    fn new(lo: Dot, hi: Dot) -> Option<Rect> {
+        if lo.0 <= hi.0 && lo.1 <= hi.1 {
+            Some(Rect { lo, hi })
+        } else {
+            None
+        }
+    }
+}
+
+impl Fold {
+    fn moves(self, (x, y): Dot) -> bool {
+        match self {
+            Fold::X(f) => x > f,
+            Fold::Y(f) => y > f,
+            Fold::Diagonal => x > y,
+            Fold::AntiDiagonal(c) => x + y > c,
+            Fold::Rotate(_, _) => true,
+        }
+    }
+
+    fn reflect(self, (x, y): Dot) -> Dot {
+        match self {
+            Fold::X(f) => ((2 * f) - x, y),
+            Fold::Y(f) => (x, (2 * f) - y),
+            Fold::Diagonal => (y, x),
+            Fold::AntiDiagonal(c) => (c - y, c - x),
+            Fold::Rotate(px, py) => ((2 * px) - x, (2 * py) - y),
+        }
+    }
+
+    // The boxes of cells that stay put and that get moved. Cells on an x= or
+    // y= fold line are in neither, the crease disappears like in the puzzle.
+    fn sides(self, Bounds { width, height }: Bounds) -> (Option<Rect>, Option<Rect>) {
+        let (w, h) = (width - 1, height - 1);
+        if w < 0 || h < 0 {
+            return (None, None);
+        }
+        match self {
+            Fold::X(f) => (
+                Rect::new((0, 0), (f.min(width) - 1, h)),
+                Rect::new(((f + 1).max(0), 0), (w, h)),
+            ),
+            Fold::Y(f) => (
+                Rect::new((0, 0), (w, f.min(height) - 1)),
+                Rect::new((0, (f + 1).max(0)), (w, h)),
+            ),
+            Fold::Diagonal => (
+                Rect::new((0, 0), (w.min(h), h)),
+                Rect::new((1, 0), (w, h.min(w - 1))),
+            ),
+            Fold::AntiDiagonal(c) => (
+                Rect::new((0, 0), (w.min(c), h.min(c))),
+                Rect::new(((c + 1 - h).max(0), (c + 1 - w).max(0)), (w, h)),
+            ),
+            Fold::Rotate(_, _) => (None, Rect::new((0, 0), (w, h))),
+        }
+    }
+}
+
+#[test]
+fn test_bounds() {
+    let dots = [(0, 0), (1, 2)];
+    assert_eq!(
+        Bounds::new(&dots, &[]),
+        Bounds {
+            width: 2,
+            height: 3
+        }
+    );
+
+    let folds = [Fold::Y(7), Fold::X(5), Fold::Y(3)];
+    let bounds = Bounds::new(&dots, &folds);
+    assert_eq!(
+        bounds,
+        Bounds {
+            width: 11,
+            height: 15
+        }
+    );
+
+    // Without the folds the blank rows below the dots would be lost
+    let mut paper = Paper::new(&dots, &folds);
+    for &fold in &folds {
+        paper = paper.fold(fold);
+    }
+    assert_eq!(paper.render(), "#....\n.....\n.#...\n");
+}
+
+// Papers with at most this many cells are stored as a bitset, anything larger
+// falls back to a sorted list of the dots.
+const DENSE_LIMIT: usize = 1 << 26;
+
+#[derive(Debug, Clone)]
+enum Cells {
+    Dense(Vec<u64>),
+    Sparse(Vec<Dot>),
+}
+
+#[derive(Debug, Clone)]
+struct Paper {
+    bounds: Bounds,
+    cells: Cells,
+}
 
-// Assuming fold always happens on halfway point
-fn fold_map(dots: &mut Vec<(i32, i32)>, fold: Fold) {
-    for (x, y) in dots.iter_mut() {
-        match fold {
-            Fold::X(f) => {
-                if *x > f {
-                    *x = (2 * f) - *x
+impl Paper {
+    fn empty(bounds: Bounds) -> Self {
+        let cells = if bounds.cells() <= DENSE_LIMIT {
+            Cells::Dense(vec![0; bounds.cells().div_ceil(64)])
+        } else {
+            Cells::Sparse(Vec::new())
+        };
+        Self { bounds, cells }
+    }
+
+    fn new(dots: &[Dot], folds: &[Fold]) -> Self {
+        Self::from_dots(Bounds::new(dots, folds), dots.to_vec())
+    }
+
+    fn from_dots(bounds: Bounds, mut dots: Vec<Dot>) -> Self {
+        let mut paper = Self::empty(bounds);
+        match &mut paper.cells {
+            Cells::Dense(_) => {
+                for dot in dots {
+                    paper.set(dot);
                 }
             }
-            Fold::Y(f) => {
-                if *y > f {
-                    *y = (2 * f) - *y
+            Cells::Sparse(cells) => {
+                dots.retain(|&dot| bounds.contains(dot));
+                dots.sort_unstable();
+                dots.dedup();
+                *cells = dots;
+            }
+        }
+        paper
+    }
+
+    fn index(&self, (x, y): Dot) -> usize {
+        y as usize * self.bounds.width as usize + x as usize
+    }
+
+    fn set(&mut self, dot: Dot) {
+        if !self.bounds.contains(dot) {
+            return;
+        }
+        let i = self.index(dot);
+        match &mut self.cells {
+            Cells::Dense(bits) => bits[i / 64] |= 1 << (i % 64),
+            Cells::Sparse(dots) => {
+                if let Err(pos) = dots.binary_search(&dot) {
+                    dots.insert(pos, dot);
                 }
             }
         }
     }
+
+    fn for_each_dot(&self, mut f: impl FnMut(Dot)) {
+        match &self.cells {
+            Cells::Dense(bits) => {
+                let width = self.bounds.width as usize;
+                for (w, &word) in bits.iter().enumerate() {
+                    let mut word = word;
+                    while word != 0 {
+                        let i = w * 64 + word.trailing_zeros() as usize;
+                        f(((i % width) as i32, (i / width) as i32));
+                        word &= word - 1;
+                    }
+                }
+            }
+            Cells::Sparse(dots) => dots.iter().for_each(|&dot| f(dot)),
+        }
+    }
+
+    // Sorted the same way fold_map leaves its dots
+    #[cfg(test)]
+    fn dots(&self) -> Vec<Dot> {
+        let mut dots = Vec::new();
+        self.for_each_dot(|dot| dots.push(dot));
+        if let Cells::Dense(_) = self.cells {
+            dots.sort_unstable();
+        }
+        dots
+    }
+
+    fn count(&self) -> usize {
+        match &self.cells {
+            Cells::Dense(bits) => bits.iter().map(|w| w.count_ones() as usize).sum(),
+            Cells::Sparse(dots) => dots.len(),
+        }
+    }
+
+    fn fold(&self, fold: Fold) -> Paper {
+        match &self.cells {
+            Cells::Dense(_) => {
+                let (bounds, offset) = self.bounds.fold(fold);
+                let mut paper = Paper::empty(bounds);
+                self.for_each_dot(|dot| paper.set(fold_point(dot, fold, offset)));
+                paper
+            }
+            Cells::Sparse(dots) => {
+                let mut dots = dots.clone();
+                let bounds = fold_map(&mut dots, self.bounds, fold);
+                Paper::from_dots(bounds, dots)
+            }
+        }
+    }
+
+    fn render(&self) -> String {
+        let width = self.bounds.width as usize;
+        let height = self.bounds.height as usize;
+        let mut out = vec![b'.'; (width + 1) * height];
+        for y in 0..height {
+            out[y * (width + 1) + width] = b'\n';
+        }
+        self.for_each_dot(|(x, y)| out[y as usize * (width + 1) + x as usize] = b'#');
+        String::from_utf8(out).expect("Rendered paper is ASCII")
+    }
+
+    fn bitmap(&self) -> Bitmap {
+        let mut bitmap = Bitmap::new(self.bounds.width as usize, self.bounds.height as usize);
+        self.for_each_dot(|(x, y)| bitmap.set(x as usize, y as usize));
+        bitmap
+    }
+}
+
+// Folding along a line keeps the cells in front of it and mirrors the ones
+// behind it, so the result covers whichever side is longer
+#[test]
+fn test_fold_axis() {
+    let fold_axis = |line, width| {
+        let (bounds, (offset, _)) = Bounds { width, height: 1 }.fold(Fold::X(line));
+        (bounds.width, offset)
+    };
+    assert_eq!(fold_axis(7, 15), (7, 0));
+    assert_eq!(fold_axis(3, 11), (7, 4));
+    assert_eq!(fold_axis(8, 11), (8, 0));
+    assert_eq!(fold_axis(20, 11), (11, 0));
+    assert_eq!(fold_axis(0, 5), (4, 4));
+    assert_eq!(fold_axis(0, 1), (0, 0));
+}
+
+// Dots on an x= or y= fold line end up outside the new bounds
+fn fold_point(dot: Dot, fold: Fold, (dx, dy): Dot) -> Dot {
+    let (x, y) = if fold.moves(dot) {
+        fold.reflect(dot)
+    } else {
+        dot
+    };
+    (x + dx, y + dy)
+}
+
+fn fold_map(dots: &mut Vec<(i32, i32)>, bounds: Bounds, fold: Fold) -> Bounds {
+    let (bounds, offset) = bounds.fold(fold);
+    for dot in dots.iter_mut() {
+        *dot = fold_point(*dot, fold, offset);
+    }
     dots.sort_unstable();
     dots.dedup();
+    bounds
 }
 
 #[test]
 fn test_fold() {
     {
         let mut v = vec![(0, 0), (20, 10)];
-        fold_map(&mut v, Fold::X(10));
+        fold_map(
+            &mut v,
+            Bounds {
+                width: 21,
+                height: 11,
+            },
+            Fold::X(10),
+        );
         assert_eq!(v, vec![(0, 0), (0, 10)]);
     }
     {
         let mut v = vec![(0, 0), (5, 10)];
-        fold_map(&mut v, Fold::X(10));
+        fold_map(
+            &mut v,
+            Bounds {
+                width: 21,
+                height: 11,
+            },
+            Fold::X(10),
+        );
         assert_eq!(v, vec![(0, 0), (5, 10)]);
     }
     {
         let mut v = vec![(0, 0), (20, 0)];
-        fold_map(&mut v, Fold::X(10));
+        fold_map(
+            &mut v,
+            Bounds {
+                width: 21,
+                height: 11,
+            },
+            Fold::X(10),
+        );
         assert_eq!(v, vec![(0, 0)]);
     }
 }
 
-// Assuming sorted
-fn print_map(dots: &[(i32, i32)], x_size: i32, y_size: i32) {
-    for y in 0..y_size {
-        for x in 0..x_size {
-            if dots.contains(&(x, y)) {
-                print!("#");
-            } else {
-                print!(".");
+#[cfg(test)]
+const EXAMPLE: &str = "6,10
+0,14
+9,10
+0,3
+10,4
+4,11
+6,0
+6,12
+4,1
+0,13
+10,12
+3,4
+3,0
+8,4
+1,10
+2,14
+8,10
+9,0
+
+fold along y=7
+fold along x=5";
+
+#[test]
+fn test_paper() {
+    let (_, (dots, folds)) = read(EXAMPLE).unwrap();
+    let mut paper = Paper::new(&dots, &folds);
+    assert_eq!(
+        paper.bounds,
+        Bounds {
+            width: 11,
+            height: 15
+        }
+    );
+    assert_eq!(paper.count(), 18);
+
+    paper = paper.fold(folds[0]);
+    assert_eq!(paper.count(), 17);
+    paper = paper.fold(folds[1]);
+    assert_eq!(paper.count(), 16);
+    assert_eq!(
+        paper.render(),
+        "#####\n#...#\n#...#\n#...#\n#####\n.....\n.....\n"
+    );
+}
+
+#[test]
+fn test_sparse_paper() {
+    let mut paper = Paper::new(&[(0, 0), (3, 1), (20_000, 20_000)], &[]);
+    assert!(matches!(paper.cells, Cells::Sparse(_)));
+    assert_eq!(paper.count(), 3);
+
+    paper = paper.fold(Fold::X(10_000)).fold(Fold::Y(10_000));
+    assert!(matches!(paper.cells, Cells::Sparse(_)));
+    assert_eq!(paper.dots(), vec![(0, 0), (3, 1)]);
+
+    paper = paper.fold(Fold::X(5_000));
+    assert!(matches!(paper.cells, Cells::Dense(_)));
+    assert_eq!(paper.dots(), vec![(0, 0), (3, 1)]);
+    assert_eq!(paper.count(), 2);
+}
+
+#[test]
+fn test_asymmetric_fold() {
+    let paper = Paper::new(&[(0, 0), (10, 0), (9, 1), (0, 4)], &[]);
+
+    let folded = paper.fold(Fold::X(3));
+    assert_eq!(
+        folded.bounds,
+        Bounds {
+            width: 7,
+            height: 5
+        }
+    );
+    assert_eq!(folded.dots(), vec![(0, 0), (1, 1), (4, 0), (4, 4)]);
+
+    let folded = folded.fold(Fold::Y(1));
+    assert_eq!(
+        folded.bounds,
+        Bounds {
+            width: 7,
+            height: 3
+        }
+    );
+    assert_eq!(folded.render(), "....#..\n.......\n#...#..\n");
+
+    let mut dots = paper.dots();
+    assert_eq!(
+        fold_map(&mut dots, paper.bounds, Fold::X(3)),
+        Bounds {
+            width: 7,
+            height: 5
+        }
+    );
+    assert_eq!(dots, vec![(0, 0), (1, 1), (4, 0), (4, 4)]);
+}
+
+#[test]
+fn test_diagonal_folds() {
+    let paper = Paper::new(&[(2, 0), (0, 1)], &[]);
+    let folded = paper.fold(Fold::Diagonal);
+    assert_eq!(
+        folded.bounds,
+        Bounds {
+            width: 2,
+            height: 3
+        }
+    );
+    assert_eq!(folded.render(), "..\n#.\n#.\n");
+
+    let paper = Paper::new(&[(0, 0), (3, 3), (3, 0)], &[]);
+    let folded = paper.fold(Fold::AntiDiagonal(2));
+    assert_eq!(
+        folded.bounds,
+        Bounds {
+            width: 4,
+            height: 4
+        }
+    );
+    assert_eq!(folded.dots(), vec![(0, 0), (1, 1), (3, 0)]);
+
+    let mut dots = paper.dots();
+    assert_eq!(
+        fold_map(&mut dots, paper.bounds, Fold::AntiDiagonal(2)),
+        folded.bounds
+    );
+    assert_eq!(dots, folded.dots());
+}
+
+#[test]
+fn test_rotate() {
+    let paper = Paper::new(&[(0, 0), (1, 0)], &[Fold::Y(1)]);
+    let rotated = paper.fold(Fold::Rotate(0, 0));
+    assert_eq!(
+        rotated.bounds,
+        Bounds {
+            width: 2,
+            height: 3
+        }
+    );
+    assert_eq!(rotated.render(), "..\n..\n##\n");
+
+    // Turned around (1,1) the paper lands one column to the right
+    let rotated = paper.fold(Fold::Rotate(1, 1));
+    assert_eq!(
+        rotated.bounds,
+        Bounds {
+            width: 3,
+            height: 3
+        }
+    );
+    assert_eq!(rotated.dots(), vec![(1, 2), (2, 2)]);
+
+    let rotated = paper.fold(Fold::Rotate(3, 1));
+    assert_eq!(rotated.render(), ".......\n.......\n.....##\n");
+}
+
+fn print_map(paper: &Paper) {
+    print!("{}", paper.render());
+}
+
+/********************* Unfolding *********************/
+impl fmt::Display for Fold {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fold::X(line) => write!(f, "fold along x={}", line),
+            Fold::Y(line) => write!(f, "fold along y={}", line),
+            Fold::Diagonal => write!(f, "fold along x=y"),
+            Fold::AntiDiagonal(c) => write!(f, "fold along x=-y{:+}", c),
+            Fold::Rotate(x, y) => write!(f, "fold along point={},{}", x, y),
+        }
+    }
+}
+
+#[test]
+fn test_display_fold() {
+    for fold in [
+        Fold::X(5),
+        Fold::Y(7),
+        Fold::Diagonal,
+        Fold::AntiDiagonal(3),
+        Fold::AntiDiagonal(-3),
+        Fold::Rotate(2, 4),
+    ] {
+        assert_eq!(read_fold(&fold.to_string()), Ok(("", fold)));
+    }
+}
+
+// For every dot of the folded paper, the places on an `original` paper that
+// fold onto it. Folding is a function, so these never overlap, and picking
+// one place per dot gives a minimal set of dots that folds into `paper`.
+fn unfold_choices(paper: &Paper, original: Bounds, folds: &[Fold]) -> Vec<Vec<Dot>> {
+    let history = original.history(folds);
+    let mut choices = Vec::new();
+    paper.for_each_dot(|dot| {
+        let mut places = vec![dot];
+        for (i, &fold) in folds.iter().enumerate().rev() {
+            let before = history[i];
+            let (_, (dx, dy)) = before.fold(fold);
+            places = places
+                .into_iter()
+                .flat_map(|(x, y)| {
+                    let place = (x - dx, y - dy);
+                    [place, fold.reflect(place)].into_iter().filter(move |&p| {
+                        before.contains(p) && fold_point(p, fold, (dx, dy)) == (x, y)
+                    })
+                })
+                .collect();
+            places.sort_unstable();
+            places.dedup();
+        }
+        choices.push(places);
+    });
+    choices
+}
+
+// Every minimal set of original dots, one place per folded dot. There are as
+// many as the product of the number of choices.
+fn unfoldings(choices: &[Vec<Dot>]) -> impl Iterator<Item = Vec<Dot>> + '_ {
+    let mut picks = if choices.iter().any(|places| places.is_empty()) {
+        None
+    } else {
+        Some(vec![0; choices.len()])
+    };
+    std::iter::from_fn(move || {
+        let current = picks.clone()?;
+        let dots = current
+            .iter()
+            .zip(choices)
+            .map(|(&pick, places)| places[pick])
+            .collect();
+
+        // Advance like an odometer, stopping once every digit wrapped
+        let next = picks.as_mut().unwrap();
+        let mut wrapped = true;
+        for (pick, places) in next.iter_mut().zip(choices) {
+            *pick += 1;
+            if *pick < places.len() {
+                wrapped = false;
+                break;
+            }
+            *pick = 0;
+        }
+        if wrapped {
+            picks = None;
+        }
+        Some(dots)
+    })
+}
+
+// One minimal set of original dots picked at random, None if some folded dot
+// cannot be reached from the original paper
+fn sample_unfolding(choices: &[Vec<Dot>], seed: u64) -> Option<Vec<Dot>> {
+    // splitmix64, which gives every seed its own stream
+    let mut state = seed;
+    choices
+        .iter()
+        .map(|places| {
+            state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^= z >> 31;
+            places
+                .get((z % places.len().max(1) as u64) as usize)
+                .copied()
+        })
+        .collect()
+}
+
+// Axis folds that halve the paper `rounds` times along each axis and leave
+// it at `bounds`, along with the bounds of the paper they start from
+fn puzzle_folds(bounds: Bounds, rounds: usize) -> (Bounds, Vec<Fold>) {
+    let Bounds {
+        mut width,
+        mut height,
+    } = bounds;
+    let mut folds = Vec::new();
+    for _ in 0..rounds {
+        folds.push(Fold::Y(height));
+        height = 2 * height + 1;
+        folds.push(Fold::X(width));
+        width = 2 * width + 1;
+    }
+    folds.reverse();
+    (Bounds { width, height }, folds)
+}
+
+// Puzzle input whose dots fold up to spell `word`. Without a seed the dots
+// are the first of the unfoldings rather than a random one.
+fn generate(word: &str, seed: Option<u64>) -> Result<String, String> {
+    let grid = ocr::render(word).map_err(|c| format!("No letter for '{}'", c))?;
+    let mut dots = Vec::new();
+    for (y, row) in grid.lines().enumerate() {
+        for (x, cell) in row.chars().enumerate() {
+            if cell == '#' {
+                dots.push((x as i32, y as i32));
+            }
+        }
+    }
+    let target = Paper::new(&dots, &[]);
+    let (original, folds) = puzzle_folds(target.bounds, 5);
+    let choices = unfold_choices(&target, original, &folds);
+    let dots = match seed {
+        Some(seed) => sample_unfolding(&choices, seed),
+        None => unfoldings(&choices).next(),
+    };
+    let dots = dots.expect("Puzzle folds reach every cell");
+
+    let mut input = String::new();
+    for (x, y) in dots {
+        input += &format!("{},{}\n", x, y);
+    }
+    input.push('\n');
+    for fold in folds {
+        input += &format!("{}\n", fold);
+    }
+    Ok(input)
+}
+
+#[test]
+fn test_unfold() {
+    let paper = Paper::new(&[(0, 0)], &[]);
+    let original = Bounds {
+        width: 3,
+        height: 3,
+    };
+    let folds = [Fold::X(1), Fold::Y(1)];
+    let choices = unfold_choices(&paper, original, &folds);
+    assert_eq!(choices, vec![vec![(0, 0), (0, 2), (2, 0), (2, 2)]]);
+    assert_eq!(unfoldings(&choices).count(), 4);
+
+    let (_, (dots, folds)) = read(EXAMPLE).unwrap();
+    let mut folded = Paper::new(&dots, &folds);
+    for &fold in &folds {
+        folded = folded.fold(fold);
+    }
+    let original = Paper::new(&dots, &folds).bounds;
+    let choices = unfold_choices(&folded, original, &folds);
+    assert_eq!(choices.len(), 16);
+    for seed in 0..4 {
+        let sample = sample_unfolding(&choices, seed).unwrap();
+        assert_eq!(sample.len(), 16);
+        let mut paper = Paper::from_dots(original, sample);
+        for &fold in &folds {
+            paper = paper.fold(fold);
+        }
+        assert_eq!(paper.render(), folded.render());
+    }
+}
+
+#[test]
+fn test_unfoldings() {
+    let choices = vec![vec![(0, 0), (2, 0)], vec![(1, 1)], vec![(0, 2), (2, 2)]];
+    let all: Vec<_> = unfoldings(&choices).collect();
+    assert_eq!(all.len(), 4);
+    assert_eq!(all[0], vec![(0, 0), (1, 1), (0, 2)]);
+    assert_eq!(all[3], vec![(2, 0), (1, 1), (2, 2)]);
+    assert_eq!(unfoldings(&[vec![(0, 0)], vec![]]).count(), 0);
+    assert_eq!(sample_unfolding(&[vec![(0, 0)], vec![]], 1), None);
+
+    // Neighbouring seeds, even and odd alike, pick different unfoldings
+    let choices = vec![(0..16).map(|x| (x, 0)).collect::<Vec<_>>(); 8];
+    let samples: Vec<_> = (0..8)
+        .map(|seed| sample_unfolding(&choices, seed).unwrap())
+        .collect();
+    for (i, a) in samples.iter().enumerate() {
+        for b in &samples[i + 1..] {
+            assert_ne!(a, b);
+        }
+    }
+}
+
+#[test]
+fn test_generate() {
+    for seed in [None, Some(7)] {
+        let input = generate("HI", seed).unwrap();
+        let (_, (dots, folds)) = read(&input).unwrap();
+        assert_eq!(folds.len(), 10);
+        let mut paper = Paper::new(&dots, &folds);
+        for fold in folds {
+            paper = paper.fold(fold);
+        }
+        assert_eq!(ocr::recognize(&paper.render()), Ok("HI".to_string()));
+    }
+    assert!(generate("HEY", None).is_err());
+}
+
+/********************* Animation *********************/
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum FrameFormat {
+    Text,
+    // Image file extension and the number of pixels per cell
+    Image(&'static str, usize),
+}
+
+#[derive(Debug, PartialEq)]
+enum Animation {
+    Terminal(Duration),
+    Frames(PathBuf, FrameFormat),
+}
+
+// --animate [delay in ms] or --frames <dir> [txt|ppm|pbm|png] [scale]
+fn read_animation(args: &[String]) -> Result<Option<Animation>, String> {
+    match args {
+        [] => Ok(None),
+        [flag] if flag == "--animate" => Ok(Some(Animation::Terminal(Duration::from_millis(500)))),
+        [flag, delay] if flag == "--animate" => match delay.parse() {
+            Ok(ms) => Ok(Some(Animation::Terminal(Duration::from_millis(ms)))),
+            Err(_) => Err(format!("Invalid delay '{}'", delay)),
+        },
+        [flag, dir] if flag == "--frames" => Ok(Some(Animation::Frames(
+            PathBuf::from(dir),
+            FrameFormat::Text,
+        ))),
+        [flag, dir, format, rest @ ..] if flag == "--frames" && rest.len() <= 1 => {
+            let scale = image::read_scale(rest.first())?;
+            let format = match format.as_str() {
+                "txt" if rest.is_empty() => FrameFormat::Text,
+                "ppm" => FrameFormat::Image("ppm", scale),
+                "pbm" => FrameFormat::Image("pbm", scale),
+                "png" => FrameFormat::Image("png", scale),
+                _ => return Err(format!("Unknown frame format '{}'", format)),
+            };
+            Ok(Some(Animation::Frames(PathBuf::from(dir), format)))
+        }
+        _ => Err(format!("Unknown arguments {:?}", args)),
+    }
+}
+
+#[test]
+fn test_read_animation() {
+    let args = |s: &str| s.split_whitespace().map(String::from).collect::<Vec<_>>();
+    assert_eq!(read_animation(&args("")), Ok(None));
+    assert_eq!(
+        read_animation(&args("--animate 20")),
+        Ok(Some(Animation::Terminal(Duration::from_millis(20))))
+    );
+    assert_eq!(
+        read_animation(&args("--frames out ppm")),
+        Ok(Some(Animation::Frames(
+            PathBuf::from("out"),
+            FrameFormat::Image("ppm", 1)
+        )))
+    );
+    assert_eq!(
+        read_animation(&args("--frames out png 4")),
+        Ok(Some(Animation::Frames(
+            PathBuf::from("out"),
+            FrameFormat::Image("png", 4)
+        )))
+    );
+    assert!(read_animation(&args("--animate soon")).is_err());
+    assert!(read_animation(&args("--frames out gif")).is_err());
+    assert!(read_animation(&args("--frames out pbm 0")).is_err());
+    assert!(read_animation(&args("--frames out txt 2")).is_err());
+}
+
+fn write_frame(dir: &Path, frame: usize, paper: &Paper, format: FrameFormat) -> io::Result<()> {
+    match format {
+        FrameFormat::Text => fs::write(dir.join(format!("fold_{:03}.txt", frame)), paper.render()),
+        FrameFormat::Image(ext, scale) => {
+            let path = dir.join(format!("fold_{:03}.{}", frame, ext));
+            paper.bitmap().save(&path, scale)
+        }
+    }
+}
+
+// Shows the paper before the first fold and after every fold, returning the
+// fully folded paper
+fn animate(mut paper: Paper, folds: &[Fold], animation: &Animation) -> io::Result<Paper> {
+    if let Animation::Frames(dir, _) = animation {
+        fs::create_dir_all(dir)?;
+    }
+    for frame in 0..=folds.len() {
+        if frame > 0 {
+            paper = paper.fold(folds[frame - 1]);
+        }
+        match animation {
+            Animation::Terminal(delay) => {
+                // Clear the screen and move the cursor home
+                print!("\x1b[2J\x1b[H");
+                println!("Fold {}/{}", frame, folds.len());
+                print_map(&paper);
+                thread::sleep(*delay);
             }
+            Animation::Frames(dir, format) => write_frame(dir, frame, &paper, *format)?,
         }
-        println!();
+    }
+    Ok(paper)
+}
+
+#[test]
+fn test_animate_frames() {
+    let (_, (dots, folds)) = read(EXAMPLE).unwrap();
+    let dir = std::env::temp_dir().join(format!("day13_frames_{}", std::process::id()));
+    let animation = Animation::Frames(dir.clone(), FrameFormat::Text);
+
+    let paper = animate(Paper::new(&dots, &folds), &folds, &animation).unwrap();
+    assert_eq!(paper.count(), 16);
+    assert_eq!(
+        fs::read_to_string(dir.join("fold_000.txt")).unwrap(),
+        Paper::new(&dots, &folds).render()
+    );
+    assert_eq!(
+        fs::read_to_string(dir.join("fold_002.txt")).unwrap(),
+        paper.render()
+    );
+
+    write_frame(&dir, 2, &paper, FrameFormat::Image("ppm", 1)).unwrap();
+    let ppm = fs::read(dir.join("fold_002.ppm")).unwrap();
+    assert!(ppm.starts_with(b"P6\n5 7\n255\n"));
+    assert_eq!(ppm.len(), "P6\n5 7\n255\n".len() + 5 * 7 * 3);
+
+    write_frame(&dir, 2, &paper, FrameFormat::Image("pbm", 2)).unwrap();
+    let pbm = fs::read(dir.join("fold_002.pbm")).unwrap();
+    assert!(pbm.starts_with(b"P4\n10 14\n\xff\xc0\xff\xc0\xc0\xc0"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+// --generate <word> [seed]
+fn read_generate(args: &[String]) -> Result<(String, Option<u64>), String> {
+    match args {
+        [word] => Ok((word.clone(), None)),
+        [word, seed] => match seed.parse() {
+            Ok(seed) => Ok((word.clone(), Some(seed))),
+            Err(_) => Err(format!("Invalid seed '{}'", seed)),
+        },
+        _ => Err(format!("Unknown arguments {:?}", args)),
     }
 }
 
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("--generate") {
+        let (word, seed) = read_generate(&args[1..]).unwrap_or_else(|e| panic!("{}", e));
+        print!(
+            "{}",
+            generate(&word, seed).unwrap_or_else(|e| panic!("{}", e))
+        );
+        return;
+    }
+    let export = image::read_args(&args).unwrap_or_else(|e| panic!("{}", e));
+    let animation = match export {
+        Some(_) => None,
+        None => read_animation(&args).unwrap_or_else(|e| panic!("{}", e)),
+    };
+
     let contents = fs::read_to_string("input/day13.txt").expect("Failed to read file");
-    let (_, (mut dots, folds)) = read(&contents).expect("Failed to read instructions");
+    let (_, (dots, folds)) = read(&contents).expect("Failed to read instructions");
 
-    dots.sort_unstable();
+    let mut paper = Paper::new(&dots, &folds);
+    if let Some(&fold) = folds.first() {
+        println!("Dots after first fold: {}", paper.fold(fold).count());
+    }
 
-    let mut x_size = 0;
-    let mut y_size = 0;
-    for fold in folds {
-        fold_map(&mut dots, fold);
-        match fold {
-            Fold::X(f) => x_size = f,
-            Fold::Y(f) => y_size = f,
+    match animation {
+        Some(animation) => {
+            paper = animate(paper, &folds, &animation).expect("Failed to animate folds")
+        }
+        None => {
+            for fold in folds {
+                paper = paper.fold(fold);
+            }
+            print_map(&paper);
         }
     }
+    if let Some((path, scale)) = export {
+        paper
+            .bitmap()
+            .save(&path, scale)
+            .expect("Failed to write image");
+    }
 
-    print_map(&dots, x_size, y_size);
+    match ocr::recognize(&paper.render()) {
+        Ok(code) => println!("Code: {}", code),
+        Err(e) => println!("Failed to read code: {}", e),
+    }
 }
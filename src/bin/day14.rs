@@ -1,77 +1,95 @@
-use nom::bytes::complete::tag;
-use nom::character::complete::{alpha1, anychar, newline};
+use nom::branch::alt;
+use nom::bytes::complete::{tag, take, take_while1};
+use nom::character::complete::{alpha1, char, newline};
 use nom::multi::separated_list1;
-use std::collections::HashMap;
+use num_bigint::BigUint;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
 use std::fs;
+use std::io::{self, Write};
 
 /********************* Read input *********************/
-fn read_transform(input: &str) -> nom::IResult<&str, ((char, char), char)> {
-    let (input, a) = anychar(input)?;
-    let (input, b) = anychar(input)?;
+// Elements are either single letters run together or names separated by
+// spaces, so "NNCB" and "Na Cl Na" are both templates
+fn tokenize(line: &str) -> Vec<&str> {
+    if line.contains(' ') {
+        line.split_whitespace().collect()
+    } else {
+        line.char_indices()
+            .map(|(i, c)| &line[i..i + c.len_utf8()])
+            .collect()
+    }
+}
+
+#[test]
+fn test_tokenize() {
+    assert_eq!(tokenize("NNCB"), vec!["N", "N", "C", "B"]);
+    assert_eq!(tokenize("Na Cl  H"), vec!["Na", "Cl", "H"]);
+    assert_eq!(tokenize(""), Vec::<&str>::new());
+}
+
+// A pair and the elements inserted between its two elements
+type Rule<'a> = ((&'a str, &'a str), Vec<&'a str>);
+
+// Named elements, as in "Na Cl -> H" or "Na Cl -> H O"
+fn read_spaced(input: &str) -> nom::IResult<&str, Rule<'_>> {
+    let (input, a) = alpha1(input)?;
+    let (input, _) = char(' ')(input)?;
+    let (input, b) = alpha1(input)?;
     let (input, _) = tag(" -> ")(input)?;
-    let (input, right) = anychar(input)?;
+    let (input, right) = separated_list1(char(' '), alpha1)(input)?;
 
     Ok((input, ((a, b), right)))
 }
 
-type Transforms = Vec<((char, char), char)>;
-fn read(input: &str) -> nom::IResult<&str, (&str, Transforms)> {
-    let (input, poly) = alpha1(input)?;
-    let (input, _) = newline(input)?;
-    let (input, _) = newline(input)?;
-    let (input, transforms) = separated_list1(newline, read_transform)(input)?;
+// Single letter elements, as in "NC -> H" or "NC -> HBC"
+fn read_compact(input: &str) -> nom::IResult<&str, Rule<'_>> {
+    let (input, a) = take(1usize)(input)?;
+    let (input, b) = take(1usize)(input)?;
+    let (input, _) = tag(" -> ")(input)?;
+    let (input, right) = alpha1(input)?;
 
-    Ok((input, (poly, transforms)))
+    Ok((input, ((a, b), tokenize(right))))
 }
 
-/********************* Actual work *********************/
-fn calc_diff(pair_counts: &HashMap<(char, char), isize>) -> f64 {
-    let mut counts = HashMap::new();
-    for (&(a, b), &count) in pair_counts {
-        if let Some(v) = counts.insert(a, count) {
-            *counts.get_mut(&a).unwrap() += v;
-        }
-        if let Some(v) = counts.insert(b, count) {
-            *counts.get_mut(&b).unwrap() += v;
-        }
-    }
-
-    (*counts.values().max().unwrap() as f64 / 2.0f64).ceil()
-        - (*counts.values().min().unwrap() as f64 / 2.0f64).ceil()
+fn read_transform(input: &str) -> nom::IResult<&str, Rule<'_>> {
+    alt((read_spaced, read_compact))(input)
 }
 
 #[test]
-fn test_calc_diff() {
+fn test_read_transform() {
+    assert_eq!(read_transform("CH -> B"), Ok(("", (("C", "H"), vec!["B"]))));
+    assert_eq!(
+        read_transform("Na Cl -> H"),
+        Ok(("", (("Na", "Cl"), vec!["H"])))
+    );
+    assert_eq!(
+        read_transform("H O -> Na\n"),
+        Ok(("\n", (("H", "O"), vec!["Na"])))
+    );
     assert_eq!(
-        calc_diff(&HashMap::from([(('A', 'B'), 1), (('B', 'B'), 1),])),
-        1f64
+        read_transform("AB -> CDE"),
+        Ok(("", (("A", "B"), vec!["C", "D", "E"])))
     );
+    assert_eq!(
+        read_transform("Na Cl -> H O"),
+        Ok(("", (("Na", "Cl"), vec!["H", "O"])))
+    );
+    assert!(read_transform("N -> H").is_err());
 }
 
-fn apply_n(
-    n: isize,
-    pair_counts: &mut HashMap<(char, char), isize>,
-    transforms: &HashMap<(char, char), char>,
-) {
-    for _ in 0..n {
-        let mut new_counts = HashMap::new();
-        for (&(a, b), &c) in transforms {
-            if let Some(&count) = pair_counts.get(&(a, b)) {
-                if let Some(v) = new_counts.insert((a, c), count) {
-                    *new_counts.get_mut(&(a, c)).unwrap() += v;
-                }
-                if let Some(v) = new_counts.insert((c, b), count) {
-                    *new_counts.get_mut(&(c, b)).unwrap() += v;
-                }
-            }
-        }
-        *pair_counts = new_counts;
-    }
+type Transforms<'a> = Vec<Rule<'a>>;
+fn read(input: &str) -> nom::IResult<&str, (&str, Transforms<'_>)> {
+    let (input, poly) = take_while1(|c: char| c.is_alphabetic() || c == ' ')(input)?;
+    let (input, _) = newline(input)?;
+    let (input, _) = newline(input)?;
+    let (input, transforms) = separated_list1(newline, read_transform)(input)?;
+
+    Ok((input, (poly, transforms)))
 }
 
-#[test]
-fn test() {
-    let content = "NNCB
+#[cfg(test)]
+const EXAMPLE: &str = "NNCB
 
 CH -> B
 HH -> N
@@ -90,52 +108,1247 @@ BC -> B
 CC -> N
 CN -> C";
 
-    let (_, (poly, trans)) = read(content).expect("Failed to read instructions");
-    let mut transforms = HashMap::new();
-    for (left, right) in trans {
-        transforms.insert(left, right);
+/********************* Actual work *********************/
+// Elements are interned to indices so the count of pair (a, b) can live at
+// a * elements + b in a flat array
+struct Polymer {
+    symbols: Vec<String>,
+    template: Vec<usize>,
+    inserts: Vec<Option<Vec<usize>>>,
+    // The pairs each pair turns into after one step
+    successors: Vec<Vec<usize>>,
+}
+
+impl Polymer {
+    fn new(poly: &str, transforms: &[Rule]) -> Polymer {
+        let mut symbols: Vec<String> = Vec::new();
+        let mut intern = |name: &str| match symbols.iter().position(|s| s == name) {
+            Some(i) => i,
+            None => {
+                symbols.push(name.to_string());
+                symbols.len() - 1
+            }
+        };
+        let template: Vec<_> = tokenize(poly).into_iter().map(&mut intern).collect();
+        let rules: Vec<_> = transforms
+            .iter()
+            .map(|((a, b), right)| {
+                let right: Vec<_> = right.iter().map(|c| intern(c)).collect();
+                (intern(a), intern(b), right)
+            })
+            .collect();
+
+        let n = symbols.len();
+        let mut inserts = vec![None; n * n];
+        for (a, b, right) in rules {
+            inserts[a * n + b] = Some(right);
+        }
+        let mut polymer = Polymer {
+            symbols,
+            template,
+            inserts,
+            successors: Vec::new(),
+        };
+        polymer.successors = (0..n * n)
+            .map(|pair| match polymer.grow(pair) {
+                Some(chain) => chain.windows(2).map(|w| polymer.pair(w[0], w[1])).collect(),
+                // Pairs without a rule are carried over unchanged
+                None => vec![pair],
+            })
+            .collect();
+        polymer
+    }
+
+    fn pair(&self, a: usize, b: usize) -> usize {
+        a * self.symbols.len() + b
+    }
+
+    fn split(&self, pair: usize) -> (usize, usize) {
+        (pair / self.symbols.len(), pair % self.symbols.len())
+    }
+
+    fn names(&self, pair: usize) -> (&str, &str) {
+        let (a, b) = self.split(pair);
+        (&self.symbols[a], &self.symbols[b])
+    }
+
+    // The elements a pair turns into after one step, including its ends
+    fn grow(&self, pair: usize) -> Option<Vec<usize>> {
+        let (a, b) = self.split(pair);
+        let inserted = self.inserts[pair].as_ref()?;
+        Some([&[a][..], inserted, &[b]].concat())
+    }
+
+    // Single letter elements are written run together, names with spaces
+    fn separator(&self) -> &'static str {
+        if self.symbols.iter().all(|s| s.chars().count() == 1) {
+            ""
+        } else {
+            " "
+        }
+    }
+}
+
+type PairCounts = Vec<u64>;
+
+#[derive(Debug, PartialEq)]
+struct Overflow;
+
+impl fmt::Display for Overflow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "polymer counts no longer fit in 64 bits")
+    }
+}
+
+fn calc_diff(polymer: &Polymer, pair_counts: &PairCounts) -> Result<u64, Overflow> {
+    let counts = element_counts(polymer, pair_counts)?;
+    Ok(counts.values().max().unwrap_or(&0) - counts.values().min().unwrap_or(&0))
+}
+
+#[test]
+fn test_calc_diff() {
+    let polymer = Polymer::new("ABB", &[]);
+    assert_eq!(calc_diff(&polymer, &poly_to_counts(&polymer, "ABB")), Ok(1));
+}
+
+// Every element is the first of one pair and the second of another, except
+// the ends of the template which never change
+fn element_counts<'a>(
+    polymer: &'a Polymer,
+    pair_counts: &PairCounts,
+) -> Result<HashMap<&'a str, u64>, Overflow> {
+    let mut doubled = vec![0u128; polymer.symbols.len()];
+    for (pair, &count) in pair_counts.iter().enumerate() {
+        let (a, b) = polymer.split(pair);
+        doubled[a] += count as u128;
+        doubled[b] += count as u128;
+    }
+    for end in [polymer.template.first(), polymer.template.last()]
+        .into_iter()
+        .flatten()
+    {
+        doubled[*end] += 1;
+    }
+
+    polymer
+        .symbols
+        .iter()
+        .zip(doubled)
+        .filter(|&(_, n)| n > 0)
+        .map(|(c, n)| {
+            u64::try_from(n / 2)
+                .map(|n| (c.as_str(), n))
+                .map_err(|_| Overflow)
+        })
+        .collect()
+}
+
+#[test]
+fn test_element_counts() {
+    let (_, (poly, trans)) = read(EXAMPLE).unwrap();
+    let polymer = Polymer::new(poly, &trans);
+    assert_eq!(
+        element_counts(&polymer, &poly_to_counts(&polymer, "NBCCNBBBCBHCB")),
+        Ok(HashMap::from([("N", 2), ("B", 6), ("C", 4), ("H", 1)]))
+    );
+    assert_eq!(
+        element_counts(&Polymer::new("N", &[]), &vec![0]),
+        Ok(HashMap::from([("N", 1)]))
+    );
+    let polymer = Polymer::new("AA", &[]);
+    assert_eq!(
+        element_counts(&polymer, &vec![u64::MAX - 1]),
+        Ok(HashMap::from([("A", u64::MAX)]))
+    );
+    assert_eq!(element_counts(&polymer, &vec![u64::MAX]), Err(Overflow));
+}
+
+// Elements from most to least common with their share of the polymer
+fn frequency_table(counts: &HashMap<&str, u64>) -> String {
+    let total: u128 = counts.values().map(|&n| n as u128).sum();
+    let mut rows: Vec<_> = counts.iter().collect();
+    rows.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+
+    let names = rows.iter().map(|(c, _)| c.len()).max().unwrap_or(1);
+    let width = rows.first().map_or(1, |(_, n)| n.to_string().len());
+    let mut table = String::new();
+    for (c, n) in rows {
+        let share = 100.0 * *n as f64 / total as f64;
+        table += &format!(
+            "{:<names$} {:>width$} {:6.2}%\n",
+            c,
+            n,
+            share,
+            names = names,
+            width = width
+        );
+    }
+    table
+}
+
+#[test]
+fn test_frequency_table() {
+    let counts = HashMap::from([("N", 2), ("B", 6), ("C", 4), ("H", 1)]);
+    assert_eq!(
+        frequency_table(&counts),
+        "B 6  46.15%\nC 4  30.77%\nN 2  15.38%\nH 1   7.69%\n"
+    );
+    let counts = HashMap::from([("Na", 12), ("H", 3)]);
+    assert_eq!(frequency_table(&counts), "Na 12  80.00%\nH   3  20.00%\n");
+}
+
+fn apply_n(n: isize, pair_counts: &mut PairCounts, polymer: &Polymer) -> Result<(), Overflow> {
+    let mut next: PairCounts = vec![0; pair_counts.len()];
+    for _ in 0..n {
+        next.fill(0);
+        for (pair, &count) in pair_counts.iter().enumerate() {
+            if count == 0 {
+                continue;
+            }
+            for &to in &polymer.successors[pair] {
+                next[to] = next[to].checked_add(count).ok_or(Overflow)?;
+            }
+        }
+        std::mem::swap(pair_counts, &mut next);
     }
-    let mut pair_counts = poly_to_counts(poly);
+    Ok(())
+}
 
-    apply_n(1, &mut pair_counts, &transforms);
-    assert_eq!(pair_counts, poly_to_counts("NCNBCHB"));
-    apply_n(1, &mut pair_counts, &transforms);
-    assert_eq!(pair_counts, poly_to_counts("NBCCNBBBCBHCB"));
-    apply_n(1, &mut pair_counts, &transforms);
-    assert_eq!(pair_counts, poly_to_counts("NBBBCNCCNBBNBNBBCHBHHBCHB"));
-    apply_n(1, &mut pair_counts, &transforms);
+#[test]
+fn test_overflow() {
+    let polymer = Polymer::new("AA", &[(("A", "A"), vec!["A"])]);
+    let mut pair_counts = poly_to_counts(&polymer, "AA");
+    assert_eq!(apply_n(63, &mut pair_counts, &polymer), Ok(()));
+    assert_eq!(pair_counts, vec![1 << 63]);
+    assert_eq!(calc_diff(&polymer, &pair_counts), Ok(0));
+    assert_eq!(apply_n(1, &mut pair_counts, &polymer), Err(Overflow));
+}
+
+#[test]
+fn test_missing_rules() {
+    let polymer = Polymer::new("ABC", &[(("A", "B"), vec!["C"])]);
+    let mut pair_counts = poly_to_counts(&polymer, "ABC");
+    apply_n(1, &mut pair_counts, &polymer).unwrap();
+    assert_eq!(pair_counts, poly_to_counts(&polymer, "ACBC"));
+    apply_n(5, &mut pair_counts, &polymer).unwrap();
+    assert_eq!(pair_counts, poly_to_counts(&polymer, "ACBC"));
+    assert_eq!(
+        element_counts(&polymer, &pair_counts),
+        Ok(HashMap::from([("A", 1), ("B", 1), ("C", 2)]))
+    );
+    let exact = apply_pow(&polymer, 6, &Exact);
+    for (pair, count) in pair_counts.iter().enumerate() {
+        assert_eq!(exact[pair], BigUint::from(*count));
+    }
+    assert_eq!(
+        missing_rules(&polymer),
+        vec![("A", "C"), ("B", "C"), ("C", "B")]
+    );
+
+    let (_, (poly, trans)) = read(EXAMPLE).unwrap();
+    assert_eq!(missing_rules(&Polymer::new(poly, &trans)), vec![]);
+}
+
+#[test]
+fn test() {
+    let (_, (poly, trans)) = read(EXAMPLE).expect("Failed to read instructions");
+    let polymer = Polymer::new(poly, &trans);
+    let mut pair_counts = poly_to_counts(&polymer, poly);
+
+    apply_n(1, &mut pair_counts, &polymer).unwrap();
+    assert_eq!(pair_counts, poly_to_counts(&polymer, "NCNBCHB"));
+    apply_n(1, &mut pair_counts, &polymer).unwrap();
+    assert_eq!(pair_counts, poly_to_counts(&polymer, "NBCCNBBBCBHCB"));
+    apply_n(1, &mut pair_counts, &polymer).unwrap();
     assert_eq!(
         pair_counts,
-        poly_to_counts("NBBNBNBBCCNBCNCCNBBNBBNBBBNBBNBBCBHCBHHNHCBBCBHCB")
+        poly_to_counts(&polymer, "NBBBCNCCNBBNBNBBCHBHHBCHB")
     );
+    apply_n(1, &mut pair_counts, &polymer).unwrap();
+    assert_eq!(
+        pair_counts,
+        poly_to_counts(
+            &polymer,
+            "NBBNBNBBCCNBCNCCNBBNBBNBBBNBBNBBCBHCBHHNHCBBCBHCB"
+        )
+    );
+
+    apply_n(6, &mut pair_counts, &polymer).unwrap();
+    assert_eq!(
+        element_counts(&polymer, &pair_counts),
+        Ok(HashMap::from([
+            ("B", 1749),
+            ("C", 298),
+            ("H", 161),
+            ("N", 865)
+        ]))
+    );
+    assert_eq!(calc_diff(&polymer, &pair_counts), Ok(1588));
+    apply_n(30, &mut pair_counts, &polymer).unwrap();
+    assert_eq!(calc_diff(&polymer, &pair_counts), Ok(2188189693529));
 }
 
-fn poly_to_counts(poly: &str) -> HashMap<(char, char), isize> {
-    let mut pair_counts: HashMap<(char, char), isize> = HashMap::new();
-    for sl in poly.as_bytes().windows(2) {
-        if let Some(c) = pair_counts.get_mut(&(sl[0].into(), sl[1].into())) {
-            *c += 1
-        } else {
-            pair_counts.insert((sl[0].into(), sl[1].into()), 1);
+/********************* Polymer expansion *********************/
+// The polymer after a number of steps, produced one element at a time so
+// only the path down to the current pair is kept in memory
+struct Expand<'a> {
+    polymer: &'a Polymer,
+    first: Option<usize>,
+    // Pairs still to expand with their remaining steps, next one on top
+    stack: Vec<(usize, usize, usize)>,
+}
+
+impl<'a> Iterator for Expand<'a> {
+    type Item = &'a str;
+    fn next(&mut self) -> Option<&'a str> {
+        if let Some(a) = self.first.take() {
+            return Some(&self.polymer.symbols[a]);
+        }
+        // Each pair yields its second element once fully expanded, the first
+        // one was already produced by the pair before it
+        while let Some((a, b, steps)) = self.stack.pop() {
+            match self.polymer.grow(self.polymer.pair(a, b)) {
+                Some(chain) if steps > 0 => {
+                    for w in chain.windows(2).rev() {
+                        self.stack.push((w[0], w[1], steps - 1));
+                    }
+                }
+                _ => return Some(&self.polymer.symbols[b]),
+            }
+        }
+        None
+    }
+}
+
+fn expand(polymer: &Polymer, steps: usize) -> Expand<'_> {
+    Expand {
+        polymer,
+        first: polymer.template.first().copied(),
+        stack: polymer
+            .template
+            .windows(2)
+            .rev()
+            .map(|w| (w[0], w[1], steps))
+            .collect(),
+    }
+}
+
+#[test]
+fn test_expand() {
+    let (_, (poly, trans)) = read(EXAMPLE).unwrap();
+    let polymer = Polymer::new(poly, &trans);
+    assert_eq!(expand(&polymer, 0).collect::<String>(), "NNCB");
+    assert_eq!(expand(&polymer, 1).collect::<String>(), "NCNBCHB");
+    assert_eq!(
+        expand(&polymer, 4).collect::<String>(),
+        "NBBNBNBBCCNBCNCCNBBNBBNBBBNBBNBBCBHCBHHNHCBBCBHCB"
+    );
+    assert_eq!(expand(&polymer, 10).count(), 3073);
+    assert_eq!(expand(&Polymer::new("", &[]), 3).count(), 0);
+    assert_eq!(expand(&Polymer::new("N", &[]), 3).collect::<String>(), "N");
+
+    // Cross check the pair counts against the actual polymer
+    let mut pair_counts = poly_to_counts(&polymer, poly);
+    for steps in 0..=12 {
+        let expanded: String = expand(&polymer, steps).collect();
+        assert_eq!(pair_counts, poly_to_counts(&polymer, &expanded));
+        apply_n(1, &mut pair_counts, &polymer).unwrap();
+    }
+
+    let polymer = Polymer::new("ABC", &[(("A", "B"), vec!["C"])]);
+    let mut pair_counts = poly_to_counts(&polymer, "ABC");
+    for steps in 0..4 {
+        let expanded: String = expand(&polymer, steps).collect();
+        assert_eq!(pair_counts, poly_to_counts(&polymer, &expanded));
+        apply_n(1, &mut pair_counts, &polymer).unwrap();
+    }
+}
+
+#[test]
+fn test_named_elements() {
+    let input = "Na Cl Na\n\nNa Cl -> H\nCl Na -> Cl\nH Cl -> Na";
+    let (_, (poly, trans)) = read(input).unwrap();
+    let polymer = Polymer::new(poly, &trans);
+    assert_eq!(polymer.separator(), " ");
+    assert_eq!(
+        expand(&polymer, 2).collect::<Vec<_>>(),
+        vec!["Na", "H", "Na", "Cl", "Cl", "Cl", "Na"]
+    );
+
+    let mut pair_counts = poly_to_counts(&polymer, poly);
+    for steps in 0..6 {
+        let expanded: Vec<_> = expand(&polymer, steps).collect();
+        assert_eq!(pair_counts, poly_to_counts(&polymer, &expanded.join(" ")));
+        apply_n(1, &mut pair_counts, &polymer).unwrap();
+    }
+
+    let pair_counts = apply_pow(&polymer, 2, &Exact);
+    let totals = element_totals(&polymer, &pair_counts, &Exact);
+    assert_eq!(totals["Na"], BigUint::from(3u8));
+    assert_eq!(totals["Cl"], BigUint::from(3u8));
+    assert_eq!(totals["H"], BigUint::from(1u8));
+    assert_eq!(
+        missing_rules(&polymer),
+        vec![("Cl", "Cl"), ("H", "Na"), ("Na", "H")]
+    );
+}
+
+#[test]
+fn test_multiple_inserts() {
+    let input = "AB\n\nAB -> CDE\nCD -> B\nEB -> AB\nBD -> A";
+    let (_, (poly, trans)) = read(input).unwrap();
+    let polymer = Polymer::new(poly, &trans);
+    assert_eq!(expand(&polymer, 1).collect::<String>(), "ACDEB");
+    assert_eq!(expand(&polymer, 2).collect::<String>(), "ACBDEABB");
+
+    let mut pair_counts = poly_to_counts(&polymer, poly);
+    for steps in 0..8 {
+        let expanded: String = expand(&polymer, steps).collect();
+        assert_eq!(pair_counts, poly_to_counts(&polymer, &expanded));
+        let exact = apply_pow(&polymer, steps as u64, &Exact);
+        for (pair, count) in pair_counts.iter().enumerate() {
+            assert_eq!(exact[pair], BigUint::from(*count));
         }
+        apply_n(1, &mut pair_counts, &polymer).unwrap();
     }
+}
+
+/********************* Element positions *********************/
+// Length of the polymer after `steps` steps, saturating at u64::MAX
+fn polymer_len(polymer: &Polymer, steps: u64) -> u64 {
+    let pair_counts = apply_pow(polymer, steps, &Saturating);
+    let first = polymer.template.len().min(1) as u64;
+    pair_counts.iter().fold(first, |n, &c| n.saturating_add(c))
+}
+
+// How many elements each pair adds after some steps, not counting its first
+// element which belongs to the pair before it. Lengths saturate at `limit`,
+// which should be past any position that is asked for.
+//
+// Rows are only kept until two steps in a row grow every pair by the same
+// amount, as every deeper step then does too until a pair reaches the limit.
+// That happens once each pair has either saturated or grows linearly.
+struct Lengths<'a> {
+    polymer: &'a Polymer,
+    limit: u64,
+    rows: Vec<Vec<u64>>,
+    growth: Option<Vec<u64>>,
+}
 
+impl<'a> Lengths<'a> {
+    fn new(polymer: &'a Polymer, limit: u64) -> Self {
+        Lengths {
+            polymer,
+            limit,
+            rows: vec![vec![1.min(limit); polymer.successors.len()]],
+            growth: None,
+        }
+    }
+
+    fn fill(&mut self, depth: usize) {
+        while self.growth.is_none() && self.rows.len() <= depth {
+            let last = &self.rows[self.rows.len() - 1];
+            let next: Vec<u64> = self
+                .polymer
+                .successors
+                .iter()
+                .map(|to| {
+                    let len = to.iter().fold(0u64, |n, &p| n.saturating_add(last[p]));
+                    len.min(self.limit)
+                })
+                .collect();
+            let growth: Vec<u64> = next.iter().zip(last).map(|(b, a)| b - a).collect();
+            if self.rows.len() > 1 {
+                let before = &self.rows[self.rows.len() - 2];
+                if last
+                    .iter()
+                    .zip(before)
+                    .map(|(b, a)| b - a)
+                    .eq(growth.iter().copied())
+                {
+                    self.growth = Some(growth);
+                }
+            }
+            self.rows.push(next);
+        }
+    }
+
+    fn len(&mut self, depth: usize, pair: usize) -> u64 {
+        self.fill(depth);
+        let last = self.rows.len() - 1;
+        match &self.growth {
+            Some(growth) if depth > last => {
+                let steps = (depth - last) as u64;
+                let len = self.rows[last][pair].saturating_add(growth[pair].saturating_mul(steps));
+                len.min(self.limit)
+            }
+            _ => self.rows[depth][pair],
+        }
+    }
+
+    // The sub-pair one step down that position `k` of `pair` falls in, and
+    // how many elements come before it
+    fn step(&mut self, pair: usize, depth: usize, k: u64) -> Option<(usize, u64)> {
+        let polymer = self.polymer;
+        let mut skipped = 0;
+        for &to in &polymer.successors[pair] {
+            let len = self.len(depth - 1, to);
+            if k - skipped < len {
+                return Some((to, skipped));
+            }
+            skipped += len;
+        }
+        None
+    }
+
+    // Whether walking down from `pair` goes through the pairs of `cycle`,
+    // skipping the same number of elements at each step
+    fn repeats(
+        &mut self,
+        mut pair: usize,
+        mut depth: usize,
+        mut k: u64,
+        cycle: &[(usize, u64)],
+    ) -> bool {
+        for &(to, skipped) in cycle {
+            if self.step(pair, depth, k) != Some((to, skipped)) {
+                return false;
+            }
+            pair = to;
+            depth -= 1;
+            k -= skipped;
+        }
+        true
+    }
+
+    // Having walked `cycle` from `pair` at depth `d` and position `j` back to
+    // the same pair at `depth` and `k`, how many more rounds of it can be
+    // skipped. Once lengths grow steadily every length along the way is
+    // linear in the depth, capped at the limit, so if the path holds for the
+    // last round skipped it holds for every round before it as well.
+    fn rounds(
+        &mut self,
+        pair: usize,
+        (d, j): (usize, u64),
+        (depth, k): (usize, u64),
+        cycle: &[(usize, u64)],
+    ) -> usize {
+        let (len, skip) = (d - depth, j - k);
+        if self.growth.is_none() || depth < self.rows.len() - 1 {
+            return 0;
+        }
+        let mut lo = 0;
+        let mut hi = (depth + 1 - self.rows.len()) / len;
+        while lo < hi {
+            let mid = lo + (hi - lo).div_ceil(2);
+            let start = (mid as u64 - 1)
+                .checked_mul(skip)
+                .and_then(|s| k.checked_sub(s));
+            match start {
+                Some(start) if self.repeats(pair, depth - (mid - 1) * len, start, cycle) => {
+                    lo = mid
+                }
+                _ => hi = mid - 1,
+            }
+        }
+        lo
+    }
+
+    // The element at position `k` after `steps` steps, found by walking down
+    // from the template one step at a time and skipping whole pairs
+    fn element_at(&mut self, steps: usize, mut k: u64) -> Option<&'a str> {
+        let polymer = self.polymer;
+        let first = *polymer.template.first()?;
+        if k == 0 {
+            return Some(&polymer.symbols[first]);
+        }
+        k -= 1;
+
+        let mut found = None;
+        for w in polymer.template.windows(2) {
+            let pair = polymer.pair(w[0], w[1]);
+            let len = self.len(steps, pair);
+            if k < len {
+                found = Some(pair);
+                break;
+            }
+            k -= len;
+        }
+
+        // Where each pair was last passed, and the steps taken since, which
+        // are at most one per pair when the walk is going round a cycle
+        let mut seen = HashMap::new();
+        let mut path = VecDeque::new();
+        let mut pair = found?;
+        let mut depth = steps;
+        while depth > 0 && polymer.inserts[pair].is_some() {
+            if let Some((d, j)) = seen.insert(pair, (depth, k)) {
+                if d - depth <= path.len() {
+                    let cycle: Vec<_> = path.range(path.len() - (d - depth)..).copied().collect();
+                    let rounds = self.rounds(pair, (d, j), (depth, k), &cycle);
+                    if rounds > 0 {
+                        depth -= rounds * (d - depth);
+                        k -= rounds as u64 * (j - k);
+                        seen.clear();
+                        path.clear();
+                        seen.insert(pair, (depth, k));
+                    }
+                }
+            }
+
+            let (to, skipped) = self.step(pair, depth, k)?;
+            path.push_back((to, skipped));
+            if path.len() > polymer.successors.len() {
+                path.pop_front();
+            }
+            pair = to;
+            depth -= 1;
+            k -= skipped;
+        }
+        Some(&polymer.symbols[polymer.split(pair).1])
+    }
+}
+
+#[test]
+fn test_element_at() {
+    for input in [EXAMPLE, "AB\n\nAB -> CDE\nCD -> B\nEB -> AB\nBD -> A"] {
+        let (_, (poly, trans)) = read(input).unwrap();
+        let polymer = Polymer::new(poly, &trans);
+        let mut lengths = Lengths::new(&polymer, 100_000);
+        for steps in 0..8 {
+            let expanded: Vec<_> = expand(&polymer, steps).collect();
+            assert_eq!(polymer_len(&polymer, steps as u64), expanded.len() as u64);
+            for (k, &c) in expanded.iter().enumerate() {
+                assert_eq!(lengths.element_at(steps, k as u64), Some(c));
+            }
+            assert_eq!(lengths.element_at(steps, expanded.len() as u64), None);
+        }
+    }
+
+    let (_, (poly, trans)) = read(EXAMPLE).unwrap();
+    let polymer = Polymer::new(poly, &trans);
+    let mut lengths = Lengths::new(&polymer, u64::MAX);
+    let expanded: Vec<_> = expand(&polymer, 16).collect();
+    for k in [0, 1, 1000, 100_000, expanded.len() - 1] {
+        assert_eq!(lengths.element_at(16, k as u64), Some(expanded[k]));
+    }
+    assert_eq!(polymer_len(&polymer, 100), u64::MAX);
+    assert_eq!(lengths.element_at(100, 1), Some("B"));
+    assert_eq!(lengths.element_at(10, u64::MAX), None);
+
+    // A small limit saturates after a few rows
+    let mut lengths = Lengths::new(&polymer, 50);
+    for steps in 0..14 {
+        let expanded: Vec<_> = expand(&polymer, steps).take(50).collect();
+        for (k, &c) in expanded.iter().enumerate() {
+            assert_eq!(lengths.element_at(steps, k as u64), Some(c));
+        }
+    }
+    assert!(lengths.rows.len() < 10);
+
+    let polymer = Polymer::new("ABC", &[(("A", "B"), vec!["C"])]);
+    let mut lengths = Lengths::new(&polymer, u64::MAX);
+    assert_eq!(polymer_len(&polymer, 1_000_000), 4);
+    assert_eq!(lengths.element_at(1_000_000, 1), Some("C"));
+    assert!(lengths.rows.len() < 5);
+    assert_eq!(polymer_len(&Polymer::new("", &[]), 3), 0);
+}
+
+#[test]
+fn test_element_at_slow_growth() {
+    // AB grows one A per step, through a cycle of one or two pairs, and in
+    // the last one by a growing number of elements per step
+    let linear = [(("A", "B"), vec!["A"])];
+    let pairs = [(("A", "B"), vec!["C"]), (("C", "B"), vec!["A"])];
+    let quadratic = [(("A", "B"), vec!["C", "A"]), (("A", "C"), vec!["C"])];
+    for rules in [&linear[..], &pairs, &quadratic] {
+        let polymer = Polymer::new("AB", rules);
+        let mut lengths = Lengths::new(&polymer, 2000);
+        for steps in 0..50 {
+            let expanded: Vec<_> = expand(&polymer, steps).collect();
+            for (k, &c) in expanded.iter().enumerate() {
+                assert_eq!(lengths.element_at(steps, k as u64), Some(c));
+            }
+            assert_eq!(lengths.element_at(steps, expanded.len() as u64), None);
+        }
+    }
+
+    // Neither the rows nor the walk down grow with the step count
+    let polymer = Polymer::new("AB", &linear);
+    assert_eq!(polymer_len(&polymer, 2_000_000_000), 2_000_000_002);
+    let mut lengths = Lengths::new(&polymer, u64::MAX);
+    for (steps, k, c) in [
+        (100_000_000, 99_999_999, Some("A")),
+        (100_000_000, 100_000_001, Some("B")),
+        (100_000_000, 100_000_002, None),
+        (1_000_000_000_000, 999_999_999_999, Some("A")),
+        (1_000_000_000_000, 1_000_000_000_001, Some("B")),
+    ] {
+        assert_eq!(lengths.element_at(steps, k), c);
+    }
+    assert!(lengths.rows.len() < 5);
+
+    let polymer = Polymer::new("AB", &pairs);
+    let mut lengths = Lengths::new(&polymer, u64::MAX);
+    assert_eq!(lengths.element_at(1_000_000_001, 999_999_999), Some("C"));
+    assert_eq!(lengths.element_at(1_000_000_001, 1_000_000_000), Some("A"));
+    assert_eq!(lengths.element_at(1_000_000_001, 1_000_000_002), Some("B"));
+}
+
+/********************* Matrix exponentiation *********************/
+// Arithmetic for counts that may grow beyond 64 bits
+trait Counting {
+    type Count: Clone;
+    fn count(&self, n: u64) -> Self::Count;
+    fn add(&self, a: &Self::Count, b: &Self::Count) -> Self::Count;
+    fn mul(&self, a: &Self::Count, b: &Self::Count) -> Self::Count;
+}
+
+// Counts modulo a number, good for any number of steps
+struct Modulo(u64);
+
+impl Counting for Modulo {
+    type Count = u64;
+    fn count(&self, n: u64) -> u64 {
+        n % self.0
+    }
+    fn add(&self, a: &u64, b: &u64) -> u64 {
+        ((*a as u128 + *b as u128) % self.0 as u128) as u64
+    }
+    fn mul(&self, a: &u64, b: &u64) -> u64 {
+        ((*a as u128 * *b as u128) % self.0 as u128) as u64
+    }
+}
+
+// Exact counts, which grow by about a bit per step
+struct Exact;
+
+impl Counting for Exact {
+    type Count = BigUint;
+    fn count(&self, n: u64) -> BigUint {
+        BigUint::from(n)
+    }
+    fn add(&self, a: &BigUint, b: &BigUint) -> BigUint {
+        a + b
+    }
+    fn mul(&self, a: &BigUint, b: &BigUint) -> BigUint {
+        a * b
+    }
+}
+
+// Counts that stop at u64::MAX, good for lengths past any position
+struct Saturating;
+
+impl Counting for Saturating {
+    type Count = u64;
+    fn count(&self, n: u64) -> u64 {
+        n
+    }
+    fn add(&self, a: &u64, b: &u64) -> u64 {
+        a.saturating_add(*b)
+    }
+    fn mul(&self, a: &u64, b: &u64) -> u64 {
+        a.saturating_mul(*b)
+    }
+}
+
+// Exact counts grow by about a bit per step, so past this many steps only
+// counts modulo a number are worth computing
+const EXACT_STEPS: u64 = 100_000;
+
+type Matrix<T> = Vec<Vec<T>>;
+
+fn mat_mul<C: Counting>(a: &Matrix<C::Count>, b: &Matrix<C::Count>, c: &C) -> Matrix<C::Count> {
+    let n = a.len();
+    let mut out = vec![vec![c.count(0); n]; n];
+    for (i, row) in out.iter_mut().enumerate() {
+        for k in 0..n {
+            for (j, cell) in row.iter_mut().enumerate() {
+                *cell = c.add(cell, &c.mul(&a[i][k], &b[k][j]));
+            }
+        }
+    }
+    out
+}
+
+fn mat_vec<C: Counting>(a: &Matrix<C::Count>, v: &[C::Count], c: &C) -> Vec<C::Count> {
+    a.iter()
+        .map(|row| {
+            row.iter()
+                .zip(v)
+                .fold(c.count(0), |acc, (x, y)| c.add(&acc, &c.mul(x, y)))
+        })
+        .collect()
+}
+
+// Every pair the template can grow, in the order they were found
+fn reachable_pairs(polymer: &Polymer) -> Vec<usize> {
+    let mut seen = vec![false; polymer.inserts.len()];
+    let mut queue: VecDeque<_> = polymer
+        .template
+        .windows(2)
+        .map(|w| polymer.pair(w[0], w[1]))
+        .collect();
+    let mut pairs = Vec::new();
+    while let Some(pair) = queue.pop_front() {
+        if std::mem::replace(&mut seen[pair], true) {
+            continue;
+        }
+        pairs.push(pair);
+        queue.extend(&polymer.successors[pair]);
+    }
+    pairs
+}
+
+// Pairs the template can grow that no rule applies to, sorted
+fn missing_rules(polymer: &Polymer) -> Vec<(&str, &str)> {
+    let mut missing: Vec<_> = reachable_pairs(polymer)
+        .into_iter()
+        .filter(|&pair| polymer.inserts[pair].is_none())
+        .map(|pair| polymer.names(pair))
+        .collect();
+    missing.sort_unstable();
+    missing
+}
+
+// Pair counts after `steps` steps, found by raising the matrix that maps the
+// pair counts of one step to the next to the power `steps`
+fn apply_pow<C: Counting>(polymer: &Polymer, mut steps: u64, counting: &C) -> Vec<C::Count> {
+    let pairs = reachable_pairs(polymer);
+    let mut index = vec![0; polymer.inserts.len()];
+    for (i, &pair) in pairs.iter().enumerate() {
+        index[pair] = i;
+    }
+
+    // matrix[to][from] is how many `to` pairs one `from` pair turns into
+    let mut matrix = vec![vec![0u64; pairs.len()]; pairs.len()];
+    for (from, &pair) in pairs.iter().enumerate() {
+        for &to in &polymer.successors[pair] {
+            matrix[index[to]][from] += 1;
+        }
+    }
+    let mut power: Matrix<C::Count> = matrix
+        .iter()
+        .map(|row| row.iter().map(|&n| counting.count(n)).collect())
+        .collect();
+
+    let initial = poly_counts(polymer, &polymer.template);
+    let mut counts: Vec<_> = pairs
+        .iter()
+        .map(|&pair| counting.count(initial[pair]))
+        .collect();
+    while steps > 0 {
+        if steps & 1 == 1 {
+            counts = mat_vec(&power, &counts, counting);
+        }
+        steps >>= 1;
+        if steps > 0 {
+            power = mat_mul(&power, &power, counting);
+        }
+    }
+
+    let mut dense = vec![counting.count(0); polymer.inserts.len()];
+    for (pair, count) in pairs.into_iter().zip(counts) {
+        dense[pair] = count;
+    }
+    dense
+}
+
+// Each element starts one pair, apart from the last one of the template
+fn element_totals<'a, C: Counting>(
+    polymer: &'a Polymer,
+    pair_counts: &[C::Count],
+    counting: &C,
+) -> HashMap<&'a str, C::Count> {
+    let mut totals = vec![counting.count(0); polymer.symbols.len()];
+    for (pair, count) in pair_counts.iter().enumerate() {
+        let (a, _) = polymer.split(pair);
+        totals[a] = counting.add(&totals[a], count);
+    }
+    if let Some(&last) = polymer.template.last() {
+        totals[last] = counting.add(&totals[last], &counting.count(1));
+    }
+    polymer
+        .symbols
+        .iter()
+        .map(String::as_str)
+        .zip(totals)
+        .collect()
+}
+
+#[test]
+fn test_apply_pow() {
+    let (_, (poly, trans)) = read(EXAMPLE).unwrap();
+    let polymer = Polymer::new(poly, &trans);
+
+    let mut pair_counts = poly_to_counts(&polymer, poly);
+    let mut done = 0;
+    for steps in [0, 1, 10, 40] {
+        apply_n(steps - done, &mut pair_counts, &polymer).unwrap();
+        done = steps;
+        let exact = apply_pow(&polymer, steps as u64, &Exact);
+        let modulo = apply_pow(&polymer, steps as u64, &Modulo(1_000_003));
+        for (pair, count) in pair_counts.iter().enumerate() {
+            assert_eq!(exact[pair], BigUint::from(*count));
+            assert_eq!(modulo[pair], count % 1_000_003);
+        }
+    }
+
+    let totals = element_totals(&polymer, &apply_pow(&polymer, 10, &Exact), &Exact);
+    assert_eq!(totals["B"], BigUint::from(1749u32));
+    assert_eq!(totals["H"], BigUint::from(161u32));
+
+    // A trillion steps only takes 40 squarings. Every pair becomes two each
+    // step, so the polymer is 3 * 2^steps + 1 elements long.
+    let steps = 1_000_000_000_000u64;
+    let p = 1_000_000_007u64;
+    let counting = Modulo(p);
+    let totals = element_totals(&polymer, &apply_pow(&polymer, steps, &counting), &counting);
+    assert_eq!(totals.len(), 4);
+    let length = totals.values().fold(0, |n, c| counting.add(&n, c));
+    let doubled = BigUint::from(2u8).modpow(&BigUint::from(steps), &BigUint::from(p));
+    assert_eq!(BigUint::from(length), (doubled * 3u8 + 1u8) % p);
+
+    // Pair counts modulo 2 go round a cycle, which gives the same answer
+    let mut counts: Vec<u64> = poly_to_counts(&polymer, poly)
+        .iter()
+        .map(|n| n % 2)
+        .collect();
+    let mut seen = HashMap::new();
+    let mut history = Vec::new();
+    while !seen.contains_key(&counts) {
+        seen.insert(counts.clone(), history.len());
+        history.push(counts.clone());
+        let mut next = vec![0; counts.len()];
+        for (pair, &n) in counts.iter().enumerate() {
+            for &to in &polymer.successors[pair] {
+                next[to] = (next[to] + n) % 2;
+            }
+        }
+        counts = next;
+    }
+    let start = seen[&counts] as u64;
+    let period = history.len() as u64 - start;
+    let index = start + (steps - start) % period;
+    assert_eq!(
+        apply_pow(&polymer, steps, &Modulo(2)),
+        history[index as usize]
+    );
+}
+
+/********************* Growth analysis *********************/
+struct Analysis<'a> {
+    // Pairs the rules mention that the template never grows
+    unreachable: Vec<(&'a str, &'a str)>,
+    // Pairs that die out, with the last step they appear in
+    extinct: Vec<((&'a str, &'a str), usize)>,
+    // How much longer the polymer gets with each step in the long run
+    growth: f64,
+    // Long run share of each element, most common first
+    shares: Vec<(&'a str, f64)>,
+}
+
+fn analyse(polymer: &Polymer) -> Analysis<'_> {
+    let pairs = polymer.successors.len();
+    let reachable = reachable_pairs(polymer);
+    let mut is_reachable = vec![false; pairs];
+    for &pair in &reachable {
+        is_reachable[pair] = true;
+    }
+
+    let mut unreachable: Vec<_> = (0..pairs)
+        .filter(|&pair| polymer.inserts[pair].is_some())
+        .flat_map(|pair| [pair].into_iter().chain(polymer.successors[pair].clone()))
+        .filter(|&pair| !is_reachable[pair])
+        .collect();
+    unreachable.sort_unstable();
+    unreachable.dedup();
+
+    // A pair keeps appearing forever exactly when it can be grown from a pair
+    // that grows itself again
+    let grows_from = |start: &[usize]| {
+        let mut seen = vec![false; pairs];
+        let mut stack = start.to_vec();
+        while let Some(pair) = stack.pop() {
+            if !std::mem::replace(&mut seen[pair], true) {
+                stack.extend(&polymer.successors[pair]);
+            }
+        }
+        seen
+    };
+    let cycles: Vec<_> = reachable
+        .iter()
+        .copied()
+        .filter(|&pair| grows_from(&polymer.successors[pair])[pair])
+        .collect();
+    let persistent = grows_from(&cycles);
+
+    // The others only lie on paths without cycles, so they are all gone
+    // after as many steps as there are pairs
+    let mut last_seen = vec![0; pairs];
+    let mut present = poly_counts(polymer, &polymer.template);
+    for step in 0..=pairs {
+        let mut next = vec![0; pairs];
+        for (pair, &count) in present.iter().enumerate() {
+            if count > 0 {
+                last_seen[pair] = step;
+                for &to in &polymer.successors[pair] {
+                    next[to] = 1;
+                }
+            }
+        }
+        present = next;
+    }
+    let mut extinct: Vec<_> = reachable
+        .iter()
+        .filter(|&&pair| !persistent[pair])
+        .map(|&pair| (polymer.names(pair), last_seen[pair]))
+        .collect();
+    extinct.sort_unstable();
+
+    // Power iteration on the step plus the identity, which has the same
+    // dominant eigenvector but cannot oscillate between states
+    let step = |v: &[f64]| {
+        let mut next = vec![0.0; pairs];
+        for (pair, &x) in v.iter().enumerate() {
+            for &to in &polymer.successors[pair] {
+                next[to] += x;
+            }
+        }
+        next
+    };
+    let normalise = |v: Vec<f64>| {
+        let total: f64 = v.iter().sum();
+        v.into_iter().map(|x| x / total).collect::<Vec<_>>()
+    };
+    let initial = poly_counts(polymer, &polymer.template);
+    let mut v: Vec<f64> = initial.iter().map(|&n| n as f64).collect();
+    let mut growth = 1.0;
+    if v.iter().sum::<f64>() > 0.0 {
+        v = normalise(v);
+        for _ in 0..100_000 {
+            let next = step(&v);
+            let next = normalise(v.iter().zip(next).map(|(x, y)| x + y).collect());
+            let delta = v.iter().zip(&next).map(|(x, y)| (x - y).abs()).sum::<f64>();
+            v = next;
+            if delta < 1e-13 {
+                break;
+            }
+        }
+        growth = step(&v).iter().sum();
+    }
+
+    // Each element starts a pair, a lone element has no pairs at all
+    let mut weights = vec![0.0; polymer.symbols.len()];
+    for (pair, &x) in v.iter().enumerate() {
+        weights[polymer.split(pair).0] += x;
+    }
+    if weights.iter().sum::<f64>() == 0.0 {
+        for &c in &polymer.template {
+            weights[c] += 1.0;
+        }
+    }
+    let total: f64 = weights.iter().sum();
+    let mut shares: Vec<_> = polymer
+        .symbols
+        .iter()
+        .zip(weights)
+        .filter(|&(_, w)| w > 0.0)
+        .map(|(c, w)| (c.as_str(), w / total))
+        .collect();
+    shares.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(b.0)));
+
+    Analysis {
+        unreachable: unreachable.into_iter().map(|p| polymer.names(p)).collect(),
+        extinct,
+        growth,
+        shares,
+    }
+}
+
+#[test]
+fn test_analyse() {
+    let input = "AB\n\nAB -> C\nAC -> C\nCC -> C\nCB -> B\nDD -> A";
+    let (_, (poly, trans)) = read(input).unwrap();
+    let polymer = Polymer::new(poly, &trans);
+    let analysis = analyse(&polymer);
+    assert_eq!(
+        analysis.unreachable,
+        vec![("A", "D"), ("D", "A"), ("D", "D")]
+    );
+    assert_eq!(analysis.extinct, vec![(("A", "B"), 0)]);
+    assert!((analysis.growth - 2.0).abs() < 1e-9);
+    assert_eq!(analysis.shares[0].0, "C");
+    assert!(analysis.shares[0].1 > 0.99);
+
+    let (_, (poly, trans)) = read(EXAMPLE).unwrap();
+    let polymer = Polymer::new(poly, &trans);
+    let analysis = analyse(&polymer);
+    assert_eq!(analysis.unreachable, vec![]);
+    assert!((analysis.growth - 2.0).abs() < 1e-9);
+    assert_eq!(analysis.shares[0].0, "B");
+
+    // Pairs without rules are kept, so nothing grows
+    let polymer = Polymer::new("ABC", &[(("A", "B"), vec!["C"])]);
+    let analysis = analyse(&polymer);
+    assert_eq!(analysis.extinct, vec![(("A", "B"), 0)]);
+    assert!((analysis.growth - 1.0).abs() < 1e-9);
+
+    let polymer = Polymer::new("N", &[]);
+    let analysis = analyse(&polymer);
+    assert_eq!(analysis.shares, vec![("N", 1.0)]);
+    assert_eq!(analysis.growth, 1.0);
+}
+
+// Pairs written the same way as the elements in the input
+fn format_pairs(polymer: &Polymer, pairs: &[(&str, &str)]) -> String {
+    let names: Vec<_> = pairs
+        .iter()
+        .map(|(a, b)| format!("{}{}{}", a, polymer.separator(), b))
+        .collect();
+    names.join(", ")
+}
+
+fn print_analysis(polymer: &Polymer, analysis: &Analysis) {
+    let none = |s: String| if s.is_empty() { "none".to_string() } else { s };
+    println!(
+        "Unreachable pairs: {}",
+        none(format_pairs(polymer, &analysis.unreachable))
+    );
+    let extinct: Vec<_> = analysis
+        .extinct
+        .iter()
+        .map(|&(pair, step)| {
+            format!(
+                "{} (last seen after {} steps)",
+                format_pairs(polymer, &[pair]),
+                step
+            )
+        })
+        .collect();
+    println!("Extinct pairs: {}", none(extinct.join(", ")));
+    println!("Growth rate: {:.4} per step", analysis.growth);
+    println!("Long run shares:");
+    for (c, share) in &analysis.shares {
+        println!("{} {:6.2}%", c, 100.0 * share);
+    }
+    if let Some((c, _)) = analysis.shares.first() {
+        println!("Dominant element: {}", c);
+    }
+}
+
+fn poly_counts(polymer: &Polymer, elements: &[usize]) -> PairCounts {
+    let mut pair_counts = vec![0; polymer.inserts.len()];
+    for w in elements.windows(2) {
+        pair_counts[polymer.pair(w[0], w[1])] += 1;
+    }
     pair_counts
 }
 
+#[cfg(test)]
+fn poly_to_counts(polymer: &Polymer, poly: &str) -> PairCounts {
+    let elements: Vec<_> = tokenize(poly)
+        .into_iter()
+        .map(|c| polymer.symbols.iter().position(|s| s == c).unwrap())
+        .collect();
+    poly_counts(polymer, &elements)
+}
+
+fn print_totals<T: fmt::Display>(totals: &HashMap<&str, T>) {
+    let mut elements: Vec<_> = totals.keys().collect();
+    elements.sort_unstable();
+    for c in elements {
+        println!("{} {}", c, totals[c]);
+    }
+}
+
 fn main() {
     let contents = fs::read_to_string("input/day14.txt").expect("Failed to read file");
     let (_, (poly, trans)) = read(&contents).expect("Failed to read instructions");
+    let polymer = Polymer::new(poly, &trans);
+    let missing = missing_rules(&polymer);
+    if !missing.is_empty() {
+        let pairs = format_pairs(&polymer, &missing);
+        eprintln!("No rule for {}, kept unchanged", pairs);
+    }
 
-    let mut transforms = HashMap::new();
-    for (left, right) in trans {
-        transforms.insert(left, right);
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("--analyse") {
+        print_analysis(&polymer, &analyse(&polymer));
+        return;
+    }
+    if args.first().map(String::as_str) == Some("--expand") {
+        let steps = args
+            .get(1)
+            .and_then(|steps| steps.parse().ok())
+            .expect("Usage: day14 --expand <steps>");
+        let mut out = io::BufWriter::new(io::stdout().lock());
+        for (i, c) in expand(&polymer, steps).enumerate() {
+            let sep = if i > 0 { polymer.separator() } else { "" };
+            write!(out, "{}{}", sep, c).expect("Failed to write polymer");
+        }
+        writeln!(out).expect("Failed to write polymer");
+        return;
+    }
+    if args.first().map(String::as_str) == Some("--at") {
+        let usage = "Usage: day14 --at <steps> <position>...";
+        let steps = args.get(1).and_then(|s| s.parse().ok()).expect(usage);
+        let positions: Vec<u64> = args[2..].iter().map(|k| k.parse().expect(usage)).collect();
+        let limit = positions.iter().max().map_or(0, |k| k.saturating_add(1));
+        let mut lengths = Lengths::new(&polymer, limit);
+        let length = polymer_len(&polymer, steps as u64);
+        println!("Length: {}", length);
+        for k in positions {
+            match lengths.element_at(steps, k) {
+                Some(c) if k < length => println!("{}: {}", k, c),
+                _ => println!("{}: past the end", k),
+            }
+        }
+        return;
+    }
+    let steps = args
+        .first()
+        .map(|steps| steps.parse().expect("Invalid step count"));
+    let modulus = args.get(1).map(|m| match m.parse() {
+        Ok(m) if m > 0 => m,
+        _ => panic!("Invalid modulus '{}'", m),
+    });
+    match (steps, modulus) {
+        (None, _) => {}
+        (Some(steps), None) if steps > EXACT_STEPS => {
+            panic!(
+                "Exact counts after {} steps have about {} bits each, give a modulus",
+                steps, steps
+            );
+        }
+        (Some(steps), None) => {
+            let pair_counts = apply_pow(&polymer, steps, &Exact);
+            print_totals(&element_totals(&polymer, &pair_counts, &Exact));
+            return;
+        }
+        (Some(steps), Some(m)) => {
+            let counting = Modulo(m);
+            let pair_counts = apply_pow(&polymer, steps, &counting);
+            print_totals(&element_totals(&polymer, &pair_counts, &counting));
+            return;
+        }
     }
 
-    let mut pair_counts = poly_to_counts(poly);
-    apply_n(1, &mut pair_counts, &transforms);
-    apply_n(9, &mut pair_counts, &transforms);
-    println!("Count: {}", calc_diff(&pair_counts));
-    apply_n(30, &mut pair_counts, &transforms);
-    println!("Count: {}", calc_diff(&pair_counts));
+    let mut pair_counts = poly_counts(&polymer, &polymer.template);
+    for (steps, total) in [(10, 10), (30, 40)] {
+        let report = apply_n(steps, &mut pair_counts, &polymer).and_then(|_| {
+            let diff = calc_diff(&polymer, &pair_counts)?;
+            Ok((diff, element_counts(&polymer, &pair_counts)?))
+        });
+        match report {
+            Ok((diff, counts)) => {
+                println!("Count: {}", diff);
+                print!("{}", frequency_table(&counts));
+            }
+            Err(e) => {
+                println!("After {} steps: {}", total, e);
+                break;
+            }
+        }
+    }
 }
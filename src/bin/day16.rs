@@ -1,8 +1,84 @@
 use nom::bits::complete::take;
-use nom::multi::many_m_n;
+use nom::branch::alt;
+use nom::bytes::complete::tag;
+use nom::character::complete::{char, digit1, multispace0};
+use nom::combinator::{all_consuming, map, opt, value};
+use nom::error::{Error, ErrorKind};
+use nom::multi::{many_m_n, separated_list1};
+use nom::sequence::{delimited, pair};
+use num_bigint::BigUint;
+use num_enum::TryFromPrimitive;
+use std::fmt;
 use std::fs;
 
-fn read_version(input: (&[u8], usize)) -> nom::IResult<(&[u8], usize), usize> {
+#[derive(Debug, Clone, Copy, PartialEq, TryFromPrimitive)]
+#[repr(u8)]
+enum Op {
+    Sum = 0,
+    Product = 1,
+    Minimum = 2,
+    Maximum = 3,
+    GreaterThan = 5,
+    LessThan = 6,
+    EqualTo = 7,
+}
+
+// How an operator says where its sub-packets end
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum LengthType {
+    Bits,
+    Packets,
+}
+
+impl Op {
+    fn name(&self) -> &'static str {
+        match self {
+            Op::Sum => "sum",
+            Op::Product => "product",
+            Op::Minimum => "min",
+            Op::Maximum => "max",
+            Op::GreaterThan => "greater than",
+            Op::LessThan => "less than",
+            Op::EqualTo => "equal to",
+        }
+    }
+
+    fn symbol(&self) -> &'static str {
+        match self {
+            Op::Sum => "+",
+            Op::Product => "*",
+            Op::Minimum => "min",
+            Op::Maximum => "max",
+            Op::GreaterThan => ">",
+            Op::LessThan => "<",
+            Op::EqualTo => "=",
+        }
+    }
+}
+
+// Offsets are where the packet starts in the transmission, in bits. Literals
+// keep how many groups they were sent in, as leading zero groups are allowed.
+#[derive(Debug, Clone, PartialEq)]
+enum Packet {
+    Literal {
+        version: u8,
+        offset: usize,
+        value: BigUint,
+        groups: usize,
+    },
+    Operator {
+        version: u8,
+        offset: usize,
+        op: Op,
+        length_type: LengthType,
+        children: Vec<Packet>,
+    },
+}
+
+const LITERAL: u8 = 4;
+
+/********************* Read input *********************/
+fn read_version(input: (&[u8], usize)) -> nom::IResult<(&[u8], usize), u8> {
     take(3usize)(input)
 }
 
@@ -10,102 +86,858 @@ fn read_type(input: (&[u8], usize)) -> nom::IResult<(&[u8], usize), u8> {
     take(3usize)(input)
 }
 
-fn read_literal(input: (&[u8], usize)) -> nom::IResult<(&[u8], usize), usize> {
+// Literals can have any number of groups, so they are kept whole
+fn read_literal(input: (&[u8], usize)) -> nom::IResult<(&[u8], usize), (BigUint, usize)> {
     let mut data = input;
-    let mut literal = 0usize;
+    let mut nibbles = Vec::new();
     let mut cont = 1;
     while cont == 1 {
         let (input, c) = take(1usize)(data)?;
-        let (input, nibble): ((&[u8], usize), usize) = take(4usize)(input)?;
+        let (input, nibble) = take(4usize)(input)?;
         cont = c;
-        literal <<= 4;
-        literal += nibble;
+        nibbles.push(nibble);
         data = input;
     }
 
-    Ok((data, literal))
+    let literal = BigUint::from_radix_be(&nibbles, 16).unwrap_or_default();
+    Ok((data, (literal, nibbles.len())))
 }
 
 fn calc_length(input: (&[u8], usize)) -> usize {
     input.0.len() * 8 - input.1
 }
 
-fn read_subpackets(input: (&[u8], usize)) -> nom::IResult<(&[u8], usize), Vec<(usize, usize)>> {
+// Where a parse error happened, in bits from the start of the transmission
+fn error_offset(bytes: &[u8], input: (&[u8], usize)) -> usize {
+    calc_length((bytes, 0)) - calc_length(input)
+}
+
+type Bits<'a> = (&'a [u8], usize);
+fn read_subpackets<'a>(
+    start: Bits,
+    input: Bits<'a>,
+) -> nom::IResult<Bits<'a>, (LengthType, Vec<Packet>)> {
     let (input, sub): ((&[u8], usize), usize) = take(1usize)(input)?;
     if sub == 0usize {
+        let field = input;
         let (mut input, length): ((&[u8], usize), usize) = take(15usize)(input)?;
+        // Lengths past the end of the input or the last sub-packet are
+        // reported at the length field
+        let invalid = || nom::Err::Error(Error::new(field, ErrorKind::LengthValue));
 
         // NOTE: No nice way to use take to split input
-        let goal = calc_length(input) - length;
+        let goal = calc_length(input).checked_sub(length).ok_or_else(invalid)?;
         let mut results = Vec::new();
         while goal < calc_length(input) {
-            let (data, value) = read(input)?;
+            let (data, value) = read_packet(start, input)?;
             input = data;
             results.push(value);
         }
-        Ok((input, results))
+        if calc_length(input) < goal {
+            return Err(invalid());
+        }
+        Ok((input, (LengthType::Bits, results)))
     } else {
         let (input, length): ((&[u8], usize), usize) = take(11usize)(input)?;
-        many_m_n(length, length, read)(input)
+        let (input, results) = many_m_n(length, length, |i| read_packet(start, i))(input)?;
+        Ok((input, (LengthType::Packets, results)))
     }
 }
 
-fn read(input: (&[u8], usize)) -> nom::IResult<(&[u8], usize), (usize, usize)> {
-    let (input, ver) = read_version(input)?;
-    let (input, ty) = read_type(input)?;
-    match ty {
-        4 /* Literal */ => {
-            let (input, value) = read_literal(input)?;
-            Ok((input, (value, ver)))
-        }
-        _ => {
-            let (input, values) = read_subpackets(input)?;
-            let mut ver_sum = ver;
-            for (_, version) in &values {
-                ver_sum += version;
+// Packets are numbered by their offset from the start of the transmission
+fn read_packet<'a>(start: Bits, input: Bits<'a>) -> nom::IResult<Bits<'a>, Packet> {
+    let offset = calc_length(start) - calc_length(input);
+    let (input, version) = read_version(input)?;
+    let (rest, ty) = read_type(input)?;
+    if ty == LITERAL {
+        let (rest, (value, groups)) = read_literal(rest)?;
+        return Ok((
+            rest,
+            Packet::Literal {
+                version,
+                offset,
+                value,
+                groups,
+            },
+        ));
+    }
+
+    let op = Op::try_from(ty).map_err(|_| nom::Err::Error(Error::new(input, ErrorKind::Tag)))?;
+    let (rest, (length_type, children)) = read_subpackets(start, rest)?;
+    Ok((
+        rest,
+        Packet::Operator {
+            version,
+            offset,
+            op,
+            length_type,
+            children,
+        },
+    ))
+}
+
+fn read(input: (&[u8], usize)) -> nom::IResult<(&[u8], usize), Packet> {
+    read_packet(input, input)
+}
+
+/********************* Actual work *********************/
+fn version_sum(packet: &Packet) -> usize {
+    match packet {
+        Packet::Literal { version, .. } => *version as usize,
+        Packet::Operator {
+            version, children, ..
+        } => *version as usize + children.iter().map(version_sum).sum::<usize>(),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum EvalError {
+    Overflow { offset: usize },
+    Empty { offset: usize, op: Op },
+    Comparison { offset: usize, children: usize },
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvalError::Overflow { offset } => {
+                write!(f, "packet at bit offset {} does not fit in 64 bits", offset)
             }
-            match ty {
-                0 => Ok((input, (values.iter().fold(0, |acc, x| acc + x.0), ver_sum))),
-                1 => Ok((input, (values.iter().fold(1, |acc, x| acc * x.0), ver_sum))),
-                2 => Ok((input, (*values.iter().map(|(v, _)| v).min().unwrap(), ver_sum))),
-                3 => Ok((input, (*values.iter().map(|(v, _)| v).max().unwrap(), ver_sum))),
-                5 => Ok((input, (if values[0].0 > values[1].0 { 1} else { 0}, ver_sum))),
-                6 => Ok((input, (if values[0].0 < values[1].0 { 1} else { 0}, ver_sum))),
-                7 => Ok((input, (if values[0].0 == values[1].0 { 1} else { 0}, ver_sum))),
-                _ => Ok((input, (values[0].0, ver_sum))),
+            EvalError::Empty { offset, op } => {
+                write!(f, "empty {} packet at bit offset {}", op.name(), offset)
             }
+            EvalError::Comparison { offset, children } => write!(
+                f,
+                "comparison packet at bit offset {} has {} sub-packet{}",
+                offset,
+                children,
+                if *children == 1 { "" } else { "s" }
+            ),
+        }
+    }
+}
+
+// Numbers packets can be evaluated with, None when the result does not fit
+trait Value: Ord + Sized {
+    fn literal(value: &BigUint) -> Option<Self>;
+    fn from_bool(b: bool) -> Self;
+    fn add(self, other: Self) -> Option<Self>;
+    fn mul(self, other: Self) -> Option<Self>;
+}
+
+impl Value for u64 {
+    fn literal(value: &BigUint) -> Option<u64> {
+        u64::try_from(value).ok()
+    }
+    fn from_bool(b: bool) -> u64 {
+        b as u64
+    }
+    fn add(self, other: u64) -> Option<u64> {
+        self.checked_add(other)
+    }
+    fn mul(self, other: u64) -> Option<u64> {
+        self.checked_mul(other)
+    }
+}
+
+impl Value for BigUint {
+    fn literal(value: &BigUint) -> Option<BigUint> {
+        Some(value.clone())
+    }
+    fn from_bool(b: bool) -> BigUint {
+        BigUint::from(b as u8)
+    }
+    fn add(self, other: BigUint) -> Option<BigUint> {
+        Some(self + other)
+    }
+    fn mul(self, other: BigUint) -> Option<BigUint> {
+        Some(self * other)
+    }
+}
+
+fn eval<T: Value>(packet: &Packet) -> Result<T, EvalError> {
+    let (offset, op, children) = match packet {
+        Packet::Literal { offset, value, .. } => {
+            let offset = *offset;
+            return T::literal(value).ok_or(EvalError::Overflow { offset });
         }
+        Packet::Operator {
+            offset,
+            op,
+            children,
+            ..
+        } => (*offset, *op, children),
+    };
+    let comparison = matches!(op, Op::GreaterThan | Op::LessThan | Op::EqualTo);
+    if comparison && children.len() != 2 {
+        let children = children.len();
+        return Err(EvalError::Comparison { offset, children });
+    }
+    if children.is_empty() {
+        return Err(EvalError::Empty { offset, op });
+    }
+
+    let mut values = children
+        .iter()
+        .map(eval)
+        .collect::<Result<Vec<T>, _>>()?
+        .into_iter();
+    let overflow = EvalError::Overflow { offset };
+    Ok(match op {
+        Op::Sum => values
+            .try_fold(T::from_bool(false), T::add)
+            .ok_or(overflow)?,
+        Op::Product => values
+            .try_fold(T::from_bool(true), T::mul)
+            .ok_or(overflow)?,
+        Op::Minimum => values.min().unwrap(),
+        Op::Maximum => values.max().unwrap(),
+        Op::GreaterThan => T::from_bool(values.next() > values.next()),
+        Op::LessThan => T::from_bool(values.next() < values.next()),
+        Op::EqualTo => T::from_bool(values.next() == values.next()),
+    })
+}
+
+#[test]
+fn test_read() {
+    let (_, packet) = read((&hex::decode("D2FE28").unwrap(), 0)).unwrap();
+    assert_eq!(
+        packet,
+        Packet::Literal {
+            version: 6,
+            offset: 0,
+            value: BigUint::from(2021u16),
+            groups: 3,
+        }
+    );
+
+    let (_, packet) = read((&hex::decode("38006F45291200").unwrap(), 0)).unwrap();
+    assert_eq!(
+        packet,
+        Packet::Operator {
+            version: 1,
+            offset: 0,
+            op: Op::LessThan,
+            length_type: LengthType::Bits,
+            children: vec![
+                Packet::Literal {
+                    version: 6,
+                    offset: 22,
+                    value: BigUint::from(10u8),
+                    groups: 1,
+                },
+                Packet::Literal {
+                    version: 2,
+                    offset: 33,
+                    value: BigUint::from(20u8),
+                    groups: 2,
+                },
+            ],
+        }
+    );
+
+    let (_, packet) = read((&hex::decode("EE00D40C823060").unwrap(), 0)).unwrap();
+    match packet {
+        Packet::Operator {
+            op,
+            length_type,
+            children,
+            ..
+        } => {
+            assert_eq!(op, Op::Maximum);
+            assert_eq!(length_type, LengthType::Packets);
+            assert_eq!(children.len(), 3);
+        }
+        _ => panic!("Expected an operator"),
     }
 }
 
 #[test]
 fn test() {
     let (_, value) = read((&hex::decode("D2FE28").unwrap(), 0)).unwrap();
-    assert_eq!(value.0, 2021usize);
-    assert_eq!(value.1, 6);
+    assert_eq!(eval::<u64>(&value), Ok(2021));
+    assert_eq!(version_sum(&value), 6);
 
     let (_, value) = read((&hex::decode("38006F45291200").unwrap(), 0)).unwrap();
-    //assert_eq!(value.0, 10);
-    assert_eq!(value.1, 9);
+    assert_eq!(eval::<u64>(&value), Ok(1));
+    assert_eq!(version_sum(&value), 9);
 
     let (_, value) = read((&hex::decode("EE00D40C823060").unwrap(), 0)).unwrap();
-    //assert_eq!(value.0, 1);
-    assert_eq!(value.1, 14);
+    assert_eq!(eval::<u64>(&value), Ok(3));
+    assert_eq!(version_sum(&value), 14);
 
     let (_, value) = read((&hex::decode("C200B40A82").unwrap(), 0)).unwrap();
-    assert_eq!(value.0, 3);
+    assert_eq!(eval::<u64>(&value), Ok(3));
 
     let (_, value) = read((&hex::decode("04005AC33890").unwrap(), 0)).unwrap();
-    assert_eq!(value.0, 54);
+    assert_eq!(eval::<u64>(&value), Ok(54));
 
     let (_, value) = read((&hex::decode("9C005AC2F8F0").unwrap(), 0)).unwrap();
-    assert_eq!(value.0, 0);
+    assert_eq!(eval::<u64>(&value), Ok(0));
 
     let (_, value) = read((&hex::decode("9C0141080250320F1802104A08").unwrap(), 0)).unwrap();
-    assert_eq!(value.0, 1);
+    assert_eq!(eval::<u64>(&value), Ok(1));
+}
+
+#[cfg(test)]
+fn literal(value: u64) -> Packet {
+    Packet::Literal {
+        version: 0,
+        offset: 0,
+        value: BigUint::from(value),
+        groups: 1,
+    }
+}
+
+// Written out and read back, so offsets are filled in
+#[cfg(test)]
+fn transmit(packet: &Packet) -> Packet {
+    let hex = encode(packet, Lengths::AsParsed).unwrap();
+    read((&hex::decode(hex).unwrap(), 0)).unwrap().1
+}
+
+#[test]
+fn test_overflow() {
+    // Seventeen nibbles, one more than fits in 64 bits
+    let value = BigUint::parse_bytes(b"77777777777777777", 16).unwrap();
+    let long = Packet::Literal {
+        version: 0,
+        offset: 0,
+        value,
+        groups: 17,
+    };
+    assert_eq!(transmit(&long), long);
+    assert_eq!(eval::<u64>(&long), Err(EvalError::Overflow { offset: 0 }));
+    assert_eq!(
+        eval::<BigUint>(&long).unwrap().to_str_radix(16),
+        "77777777777777777"
+    );
+
+    let product = operator(Op::Product, vec![literal(1 << 32), literal(1 << 32)]);
+    assert_eq!(
+        eval::<u64>(&product),
+        Err(EvalError::Overflow { offset: 0 })
+    );
+    assert_eq!(eval::<BigUint>(&product), Ok(BigUint::from(1u8) << 64));
+
+    let sum = operator(Op::Sum, vec![literal(u64::MAX), literal(1)]);
+    assert_eq!(
+        eval::<u64>(&operator(Op::Sum, vec![literal(u64::MAX)])),
+        Ok(u64::MAX)
+    );
+
+    // Comparisons bring big values back into range
+    let less = transmit(&operator(Op::LessThan, vec![literal(u64::MAX), sum]));
+    assert_eq!(eval::<u64>(&less), Err(EvalError::Overflow { offset: 104 }));
+    assert_eq!(eval::<BigUint>(&less), Ok(BigUint::from(1u8)));
+    assert_eq!(
+        EvalError::Overflow { offset: 104 }.to_string(),
+        "packet at bit offset 104 does not fit in 64 bits"
+    );
+}
+
+#[test]
+fn test_validate() {
+    let greater = transmit(&operator(Op::GreaterThan, vec![literal(1)]));
+    let error = eval::<u64>(&greater).unwrap_err();
+    assert_eq!(
+        error,
+        EvalError::Comparison {
+            offset: 0,
+            children: 1
+        }
+    );
+    assert_eq!(
+        error.to_string(),
+        "comparison packet at bit offset 0 has 1 sub-packet"
+    );
+
+    let equal = operator(Op::EqualTo, vec![literal(1), literal(1), literal(1)]);
+    assert_eq!(
+        eval::<BigUint>(&equal).unwrap_err().to_string(),
+        "comparison packet at bit offset 0 has 3 sub-packets"
+    );
+
+    let sum = transmit(&operator(
+        Op::Sum,
+        vec![literal(1), operator(Op::Minimum, vec![])],
+    ));
+    let error = eval::<u64>(&sum).unwrap_err();
+    assert_eq!(
+        error,
+        EvalError::Empty {
+            offset: 29,
+            op: Op::Minimum
+        }
+    );
+    assert_eq!(error.to_string(), "empty min packet at bit offset 29");
+    assert_eq!(
+        eval::<u64>(&operator(Op::Product, vec![])),
+        Err(EvalError::Empty {
+            offset: 0,
+            op: Op::Product
+        })
+    );
+
+    // A sum claiming 0x7fff bits, then one that its 11 bit literal overruns
+    for length in [0x7fff, 5] {
+        let mut bits = BitWriter::default();
+        bits.push(Op::Sum as u64, 6);
+        bits.push(0, 1);
+        bits.push(length, 15);
+        write(&literal(1), Lengths::AsParsed, &mut bits).unwrap();
+        let bytes = bits.bytes();
+        let error = match read((&bytes, 0)) {
+            Err(nom::Err::Error(error)) => error,
+            other => panic!("Expected an error, got {:?}", other),
+        };
+        assert_eq!(error.code, ErrorKind::LengthValue);
+        assert_eq!(error_offset(&bytes, error.input), 7);
+    }
+}
+
+/********************* Display *********************/
+// One packet per line, sub-packets indented below their operator
+fn tree(packet: &Packet) -> String {
+    fn lines(packet: &Packet, depth: usize, out: &mut String) {
+        let indent = "  ".repeat(depth);
+        match packet {
+            Packet::Literal {
+                version,
+                offset,
+                value,
+                ..
+            } => {
+                *out += &format!(
+                    "{}literal {} [version {}, type {}, offset {}]\n",
+                    indent, value, version, LITERAL, offset
+                );
+            }
+            Packet::Operator {
+                version,
+                offset,
+                op,
+                length_type,
+                children,
+            } => {
+                let length = match length_type {
+                    LengthType::Bits => "length in bits",
+                    LengthType::Packets => "packet count",
+                };
+                *out += &format!(
+                    "{}{} [version {}, type {}, offset {}, {}]\n",
+                    indent,
+                    op.name(),
+                    version,
+                    *op as u8,
+                    offset,
+                    length
+                );
+                for child in children {
+                    lines(child, depth + 1, out);
+                }
+            }
+        }
+    }
+
+    let mut out = String::new();
+    lines(packet, 0, &mut out);
+    out
+}
+
+// What the packet computes, as in (* (+ 1 3) (max 2 4))
+fn sexpr(packet: &Packet) -> String {
+    match packet {
+        Packet::Literal { value, .. } => value.to_string(),
+        Packet::Operator { op, children, .. } => {
+            let mut parts = vec![op.symbol().to_string()];
+            parts.extend(children.iter().map(sexpr));
+            format!("({})", parts.join(" "))
+        }
+    }
+}
+
+#[test]
+fn test_display() {
+    let (_, packet) = read((&hex::decode("38006F45291200").unwrap(), 0)).unwrap();
+    assert_eq!(
+        tree(&packet),
+        "less than [version 1, type 6, offset 0, length in bits]
+  literal 10 [version 6, type 4, offset 22]
+  literal 20 [version 2, type 4, offset 33]
+"
+    );
+    let (_, packet) = read((&hex::decode("EE00D40C823060").unwrap(), 0)).unwrap();
+    assert_eq!(
+        tree(&packet),
+        "max [version 7, type 3, offset 0, packet count]
+  literal 1 [version 2, type 4, offset 18]
+  literal 2 [version 4, type 4, offset 29]
+  literal 3 [version 1, type 4, offset 40]
+"
+    );
+
+    for (transmission, expression) in [
+        ("D2FE28", "2021"),
+        ("C200B40A82", "(+ 1 2)"),
+        ("04005AC33890", "(* 6 9)"),
+        ("880086C3E88112", "(min 7 8 9)"),
+        ("9C0141080250320F1802104A08", "(= (+ 1 3) (* 2 2))"),
+    ] {
+        let (_, packet) = read((&hex::decode(transmission).unwrap(), 0)).unwrap();
+        assert_eq!(sexpr(&packet), expression);
+    }
+}
+
+/********************* Encode *********************/
+// Which length type operators are written with
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Lengths {
+    // Whatever the packet was read with, literal groups included
+    AsParsed,
+    // A packet count where it fits, as its field is four bits shorter, and
+    // literals without leading zero groups
+    Shortest,
+}
+
+#[derive(Debug, PartialEq)]
+enum EncodeError {
+    Version(u8),
+    Oversized(LengthType, usize),
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncodeError::Version(v) => write!(f, "version {} does not fit in 3 bits", v),
+            EncodeError::Oversized(LengthType::Bits, n) => {
+                write!(f, "{} bits of sub-packets do not fit in 15 bits", n)
+            }
+            EncodeError::Oversized(LengthType::Packets, n) => {
+                write!(f, "{} sub-packets do not fit in 11 bits", n)
+            }
+        }
+    }
+}
+
+// Bits most significant first, as they are sent
+#[derive(Default)]
+struct BitWriter {
+    bits: Vec<bool>,
+}
+
+impl BitWriter {
+    fn push(&mut self, value: u64, width: usize) {
+        for i in (0..width).rev() {
+            self.bits.push(value >> i & 1 == 1);
+        }
+    }
+
+    // Padded with zeros to a whole number of bytes
+    fn bytes(&self) -> Vec<u8> {
+        self.bits
+            .chunks(8)
+            .map(|chunk| {
+                let byte = chunk.iter().fold(0, |acc, &b| acc << 1 | b as u8);
+                byte << (8 - chunk.len())
+            })
+            .collect()
+    }
+}
+
+// At least `groups` groups, padded with leading zero groups
+fn write_literal(value: &BigUint, groups: usize, out: &mut BitWriter) {
+    let mut nibbles = value.to_radix_be(16);
+    if groups > nibbles.len() {
+        nibbles.splice(0..0, vec![0; groups - nibbles.len()]);
+    }
+    for (i, &nibble) in nibbles.iter().enumerate() {
+        out.push((i + 1 < nibbles.len()) as u64, 1);
+        out.push(nibble as u64, 4);
+    }
+}
+
+fn write(packet: &Packet, lengths: Lengths, out: &mut BitWriter) -> Result<(), EncodeError> {
+    match packet {
+        Packet::Literal {
+            version,
+            value,
+            groups,
+            ..
+        } => {
+            if *version > 7 {
+                return Err(EncodeError::Version(*version));
+            }
+            out.push(*version as u64, 3);
+            out.push(LITERAL as u64, 3);
+            let groups = match lengths {
+                Lengths::AsParsed => *groups,
+                Lengths::Shortest => 1,
+            };
+            write_literal(value, groups, out);
+        }
+        Packet::Operator {
+            version,
+            op,
+            length_type,
+            children,
+            ..
+        } => {
+            if *version > 7 {
+                return Err(EncodeError::Version(*version));
+            }
+            let mut sub = BitWriter::default();
+            for child in children {
+                write(child, lengths, &mut sub)?;
+            }
+            let length_type = match lengths {
+                Lengths::AsParsed => *length_type,
+                Lengths::Shortest if children.len() < 1 << 11 => LengthType::Packets,
+                Lengths::Shortest => LengthType::Bits,
+            };
+
+            out.push(*version as u64, 3);
+            out.push(*op as u64, 3);
+            match length_type {
+                LengthType::Bits if sub.bits.len() < 1 << 15 => {
+                    out.push(0, 1);
+                    out.push(sub.bits.len() as u64, 15);
+                }
+                LengthType::Packets if children.len() < 1 << 11 => {
+                    out.push(1, 1);
+                    out.push(children.len() as u64, 11);
+                }
+                LengthType::Bits => {
+                    return Err(EncodeError::Oversized(length_type, sub.bits.len()))
+                }
+                LengthType::Packets => {
+                    return Err(EncodeError::Oversized(length_type, children.len()))
+                }
+            }
+            out.bits.extend(sub.bits);
+        }
+    }
+    Ok(())
+}
+
+fn encode(packet: &Packet, lengths: Lengths) -> Result<String, EncodeError> {
+    let mut out = BitWriter::default();
+    write(packet, lengths, &mut out)?;
+    Ok(hex::encode_upper(out.bytes()))
+}
+
+#[test]
+fn test_encode() {
+    for transmission in [
+        "D2FE28",
+        "38006F45291200",
+        "EE00D40C823060",
+        "8A004A801A8002F478",
+        "620080001611562C8802118E34",
+        "C0015000016115A2E0802F182340",
+        "A0016C880162017C3686B18A3D4780",
+        "9C0141080250320F1802104A08",
+    ] {
+        let (_, packet) = read((&hex::decode(transmission).unwrap(), 0)).unwrap();
+        assert_eq!(
+            encode(&packet, Lengths::AsParsed),
+            Ok(transmission.to_string())
+        );
+
+        let shortest = encode(&packet, Lengths::Shortest).unwrap();
+        assert!(shortest.len() <= transmission.len());
+        let (_, decoded) = read((&hex::decode(&shortest).unwrap(), 0)).unwrap();
+        assert_eq!(eval::<u64>(&decoded), eval::<u64>(&packet));
+        assert_eq!(version_sum(&decoded), version_sum(&packet));
+        assert_eq!(encode(&decoded, Lengths::AsParsed), Ok(shortest));
+    }
+
+    assert_eq!(
+        encode(&literal(0), Lengths::AsParsed),
+        Ok("1000".to_string())
+    );
+    // A five sent with a leading zero group keeps it unless shortened
+    let (_, padded) = read((&hex::decode("1205").unwrap(), 0)).unwrap();
+    assert_eq!(eval::<u64>(&padded), Ok(5));
+    assert_eq!(encode(&padded, Lengths::AsParsed), Ok("1205".to_string()));
+    assert_eq!(encode(&padded, Lengths::Shortest), Ok("10A0".to_string()));
+    let big = Packet::Literal {
+        version: 7,
+        offset: 0,
+        value: (BigUint::from(1u8) << 100) - 1u8,
+        groups: 25,
+    };
+    assert_eq!(transmit(&big), big);
+
+    let bad = Packet::Literal {
+        version: 8,
+        offset: 0,
+        value: BigUint::from(1u8),
+        groups: 1,
+    };
+    assert_eq!(
+        encode(&bad, Lengths::AsParsed),
+        Err(EncodeError::Version(8))
+    );
+    let wide = operator(Op::Sum, vec![literal(0); 1 << 11]);
+    assert_eq!(
+        encode(&wide, Lengths::AsParsed),
+        Err(EncodeError::Oversized(LengthType::Packets, 1 << 11))
+    );
+    let (_, decoded) = read((
+        &hex::decode(encode(&wide, Lengths::Shortest).unwrap()).unwrap(),
+        0,
+    ))
+    .unwrap();
+    assert_eq!(eval::<u64>(&decoded), Ok(0));
+}
+
+/********************* Compile *********************/
+// Packets built by hand start out at version 0 and offset 0
+fn operator(op: Op, children: Vec<Packet>) -> Packet {
+    Packet::Operator {
+        version: 0,
+        offset: 0,
+        op,
+        length_type: LengthType::Packets,
+        children,
+    }
+}
+
+fn ws<'a, O>(
+    inner: impl FnMut(&'a str) -> nom::IResult<&'a str, O>,
+) -> impl FnMut(&'a str) -> nom::IResult<&'a str, O> {
+    delimited(multispace0, inner, multispace0)
+}
+
+fn read_number(input: &str) -> nom::IResult<&str, Packet> {
+    map(digit1, |digits: &str| Packet::Literal {
+        version: 0,
+        offset: 0,
+        value: digits.parse().unwrap(),
+        groups: 1,
+    })(input)
+}
+
+// sum(a, b, ...), product(...), min(...) or max(...)
+fn read_call(input: &str) -> nom::IResult<&str, Packet> {
+    let (input, op) = alt((
+        value(Op::Sum, tag("sum")),
+        value(Op::Product, tag("product")),
+        value(Op::Minimum, tag("min")),
+        value(Op::Maximum, tag("max")),
+    ))(input)?;
+    let (input, _) = ws(char('('))(input)?;
+    let (input, children) = separated_list1(char(','), read_expression)(input)?;
+    let (input, _) = char(')')(input)?;
+
+    Ok((input, operator(op, children)))
+}
+
+fn read_atom(input: &str) -> nom::IResult<&str, Packet> {
+    ws(alt((
+        read_number,
+        read_call,
+        delimited(char('('), read_expression, char(')')),
+    )))(input)
+}
+
+// Runs of the same operator become one packet, so 1 + 2 + 3 is (+ 1 2 3)
+fn read_chain<'a>(
+    op: Op,
+    symbol: char,
+    operand: fn(&'a str) -> nom::IResult<&'a str, Packet>,
+) -> impl FnMut(&'a str) -> nom::IResult<&'a str, Packet> {
+    map(
+        separated_list1(char(symbol), operand),
+        move |mut operands| {
+            if operands.len() == 1 {
+                operands.remove(0)
+            } else {
+                operator(op, operands)
+            }
+        },
+    )
+}
+
+fn read_product(input: &str) -> nom::IResult<&str, Packet> {
+    read_chain(Op::Product, '*', read_atom)(input)
+}
+
+fn read_sum(input: &str) -> nom::IResult<&str, Packet> {
+    read_chain(Op::Sum, '+', read_product)(input)
+}
+
+// Comparisons bind loosest and do not chain
+fn read_expression(input: &str) -> nom::IResult<&str, Packet> {
+    let (input, left) = read_sum(input)?;
+    let compare = alt((
+        value(Op::LessThan, char('<')),
+        value(Op::GreaterThan, char('>')),
+        value(Op::EqualTo, alt((tag("=="), tag("=")))),
+    ));
+    let (input, right) = opt(pair(compare, read_sum))(input)?;
+    Ok(match right {
+        Some((op, right)) => (input, operator(op, vec![left, right])),
+        None => (input, left),
+    })
+}
+
+fn compile(source: &str) -> Result<Packet, String> {
+    match all_consuming(read_expression)(source) {
+        Ok((_, packet)) => Ok(packet),
+        Err(nom::Err::Error(e) | nom::Err::Failure(e)) => Err(format!(
+            "unexpected '{}' at column {}",
+            e.input,
+            source.len() - e.input.len() + 1
+        )),
+        Err(nom::Err::Incomplete(_)) => Err("unexpected end of expression".to_string()),
+    }
+}
+
+#[test]
+fn test_compile() {
+    for (source, expression, result) in [
+        ("sum(1, 2) * (3 < 4)", "(* (+ 1 2) (< 3 4))", 3),
+        ("1 + 2 * 3", "(+ 1 (* 2 3))", 7),
+        ("1+2+3 > 5", "(> (+ 1 2 3) 5)", 1),
+        ("max(1, min(4, 5), 2) == 4", "(= (max 1 (min 4 5) 2) 4)", 1),
+        (" product( 6 ) ", "(* 6)", 6),
+        ("((2021))", "2021", 2021),
+    ] {
+        let packet = compile(source).unwrap();
+        assert_eq!(sexpr(&packet), expression);
+        let hex = encode(&packet, Lengths::Shortest).unwrap();
+        let (_, decoded) = read((&hex::decode(hex).unwrap(), 0)).unwrap();
+        assert_eq!(sexpr(&decoded), expression);
+        assert_eq!(eval::<u64>(&decoded), Ok(result));
+    }
+
+    assert_eq!(
+        encode(&compile("2021").unwrap(), Lengths::Shortest),
+        Ok("12FE28".to_string())
+    );
+    assert_eq!(
+        compile("1 < 2 < 3"),
+        Err("unexpected '< 3' at column 7".to_string())
+    );
+    assert!(compile("foo(1)").is_err());
+    assert!(compile("sum()").is_err());
+    assert!(compile("1 +").is_err());
 }
 
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("--compile") {
+        let source = args[1..].join(" ");
+        let packet = compile(&source).unwrap_or_else(|e| panic!("{}", e));
+        match encode(&packet, Lengths::Shortest) {
+            Ok(hex) => println!("{}", hex),
+            Err(e) => panic!("Failed to encode packet: {}", e),
+        }
+        return;
+    }
+
     let mut contents = fs::read_to_string("input/day16.txt").expect("Failed to read file");
     contents.pop(); // remove \n
     let bytes = match hex::decode(&contents) {
@@ -116,6 +948,44 @@ fn main() {
         }
     };
 
-    let (_, value) = read((&bytes, 0usize)).expect("Failed to parse bytes");
-    println!("Value: {:?}", value);
+    let packet = match read((&bytes, 0usize)) {
+        Ok((_, packet)) => packet,
+        Err(nom::Err::Error(e) | nom::Err::Failure(e)) => panic!(
+            "Failed to parse bytes at bit offset {}",
+            error_offset(&bytes, e.input)
+        ),
+        Err(e) => panic!("Failed to parse bytes: {}", e),
+    };
+    let flag = args.first().map(String::as_str);
+    let lengths = match flag {
+        Some("--tree") => {
+            print!("{}", tree(&packet));
+            return;
+        }
+        Some("--sexpr") => {
+            println!("{}", sexpr(&packet));
+            return;
+        }
+        Some("--encode") => Some(Lengths::AsParsed),
+        Some("--compress") => Some(Lengths::Shortest),
+        _ => None,
+    };
+    if let Some(lengths) = lengths {
+        match encode(&packet, lengths) {
+            Ok(hex) => println!("{}", hex),
+            Err(e) => panic!("Failed to encode packet: {}", e),
+        }
+        return;
+    }
+    println!("Version sum: {}", version_sum(&packet));
+    let value = if flag == Some("--big") {
+        eval::<BigUint>(&packet).map(|value| value.to_string())
+    } else {
+        eval::<u64>(&packet).map(|value| value.to_string())
+    };
+    match value {
+        Ok(value) => println!("Value: {}", value),
+        Err(e @ EvalError::Overflow { .. }) => println!("Value: {}, try --big", e),
+        Err(e) => println!("Invalid transmission: {}", e),
+    }
 }
@@ -1,31 +1,104 @@
-use nom::character::complete::{alpha1, i32 as read_i32, newline, space1};
+use nom::character::complete::{alpha1, i32 as read_i32, space1};
 use nom::combinator::map_res;
-use nom::multi::separated_list1;
+use nom::error::ErrorKind;
 use nom::IResult;
+use std::fmt;
 use std::fs;
 
 use std::str::FromStr;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 enum Direction {
     Forward,
     Up,
     Down,
 }
 
+const DIRECTIONS: [(&str, Direction); 3] = [
+    ("forward", Direction::Forward),
+    ("down", Direction::Down),
+    ("up", Direction::Up),
+];
+
+#[derive(Debug, PartialEq)]
+struct UnknownDirection(String);
+
+impl fmt::Display for UnknownDirection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown direction '{}'", self.0)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum ReadError {
+    UnknownDirection { line: usize, token: String },
+    Malformed { line: usize, text: String },
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadError::UnknownDirection { line, token } => {
+                write!(f, "line {}: unknown direction '{}'", line, token)
+            }
+            ReadError::Malformed { line, text } => {
+                write!(f, "line {}: malformed instruction '{}'", line, text)
+            }
+        }
+    }
+}
+
 /********************* Read input *********************/
+// An abbreviation keeps the first letter of the word followed by any of the
+// remaining letters in order, so "f", "fwd" and "dn" are all accepted.
+fn abbreviates(token: &str, word: &str) -> bool {
+    let mut letters = word.chars();
+    let mut token = token.chars();
+    match token.next() {
+        Some(c) if Some(c) == letters.next() => token.all(|c| letters.any(|l| l == c)),
+        _ => false,
+    }
+}
+
+#[test]
+fn test_abbreviates() {
+    assert!(abbreviates("f", "forward"));
+    assert!(abbreviates("fwd", "forward"));
+    assert!(abbreviates("dn", "down"));
+    assert!(abbreviates("up", "up"));
+    assert!(!abbreviates("", "up"));
+    assert!(!abbreviates("wd", "forward"));
+    assert!(!abbreviates("fdw", "forward"));
+    assert!(!abbreviates("upp", "up"));
+}
+
 impl FromStr for Direction {
-    type Err = ();
+    type Err = UnknownDirection;
     fn from_str(input: &str) -> Result<Direction, Self::Err> {
-        match input {
-            "forward" => Ok(Direction::Forward),
-            "down" => Ok(Direction::Down),
-            "up" => Ok(Direction::Up),
-            _ => Err(()),
+        let token = input.to_lowercase();
+        let mut matches = DIRECTIONS
+            .iter()
+            .filter(|(word, _)| abbreviates(&token, word));
+        match (matches.next(), matches.next()) {
+            (Some(&(_, dir)), None) => Ok(dir),
+            _ => Err(UnknownDirection(input.to_string())),
         }
     }
 }
 
+#[test]
+fn test_direction_from_str() {
+    assert_eq!("Forward".parse(), Ok(Direction::Forward));
+    assert_eq!("FWD".parse(), Ok(Direction::Forward));
+    assert_eq!("f".parse(), Ok(Direction::Forward));
+    assert_eq!("dn".parse(), Ok(Direction::Down));
+    assert_eq!("U".parse(), Ok(Direction::Up));
+    assert_eq!(
+        "back".parse::<Direction>(),
+        Err(UnknownDirection("back".to_string()))
+    );
+}
+
 fn read_direction(input: &str) -> IResult<&str, Direction> {
     map_res(alpha1, Direction::from_str)(input)
 }
@@ -35,6 +108,7 @@ fn test_read_direction() {
     assert_eq!(read_direction("up"), Ok(("", Direction::Up)));
     assert_eq!(read_direction("down 42"), Ok((" 42", Direction::Down)));
     assert_eq!(read_direction("forward"), Ok(("", Direction::Forward)));
+    assert_eq!(read_direction("DN 3"), Ok((" 3", Direction::Down)));
 }
 
 fn read_line(input: &str) -> IResult<&str, (Direction, i32)> {
@@ -55,8 +129,67 @@ fn test_read_line() {
     assert_eq!(read_line("up 1\n"), Ok(("\n", (Direction::Up, 1))));
 }
 
-fn read(input: &str) -> nom::IResult<&str, Vec<(Direction, i32)>> {
-    separated_list1(newline, read_line)(input)
+fn read(input: &str) -> Result<Vec<(Direction, i32)>, ReadError> {
+    let mut instructions = Vec::new();
+    for (i, text) in input.lines().enumerate() {
+        let text = text.trim();
+        if text.is_empty() {
+            continue;
+        }
+
+        let line = i + 1;
+        match read_line(text) {
+            Ok(("", instruction)) => instructions.push(instruction),
+            // The direction was a word but not one we know
+            Err(nom::Err::Error(e)) if e.code == ErrorKind::MapRes => {
+                let token = e.input.split(|c: char| !c.is_alphabetic()).next();
+                let token = token.unwrap_or_default().to_string();
+                return Err(ReadError::UnknownDirection { line, token });
+            }
+            _ => {
+                let text = text.to_string();
+                return Err(ReadError::Malformed { line, text });
+            }
+        }
+    }
+
+    Ok(instructions)
+}
+
+#[test]
+fn test_read() {
+    assert_eq!(
+        read("forward 5\nDOWN 5\nfwd 8\nu 3\ndn 8\nF 2\n"),
+        Ok(vec![
+            (Direction::Forward, 5),
+            (Direction::Down, 5),
+            (Direction::Forward, 8),
+            (Direction::Up, 3),
+            (Direction::Down, 8),
+            (Direction::Forward, 2),
+        ])
+    );
+    assert_eq!(
+        read("forward 5\nback 2\n"),
+        Err(ReadError::UnknownDirection {
+            line: 2,
+            token: "back".to_string()
+        })
+    );
+    assert_eq!(
+        read("fwd 1\nsideways 3\n"),
+        Err(ReadError::UnknownDirection {
+            line: 2,
+            token: "sideways".to_string()
+        })
+    );
+    assert_eq!(
+        read("up 1\n\nup x\n"),
+        Err(ReadError::Malformed {
+            line: 3,
+            text: "up x".to_string()
+        })
+    );
 }
 
 /********************* Actual work *********************/
@@ -94,7 +227,10 @@ fn follow_aim(instructions: &[(Direction, i32)]) -> i32 {
 
 fn main() {
     let contents = fs::read_to_string("input/day2.txt").expect("Failed to read file");
-    let (_, instructions) = read(&contents).expect("Failed to read instructions");
+    let instructions = match read(&contents) {
+        Ok(instructions) => instructions,
+        Err(e) => panic!("Failed to read instructions: {}", e),
+    };
 
     println!("Distance: {}", follow_path(&instructions));
     println!("Aim: {}", follow_aim(&instructions));
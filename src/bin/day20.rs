@@ -1,3 +1,4 @@
+use aoc21::image::{self, Bitmap};
 use nom::character::complete::{newline, one_of};
 use nom::combinator::map;
 use nom::multi::{many0, separated_list0};
@@ -39,7 +40,7 @@ fn points_to_value(map: &[Vec<u8>], x: isize, y: isize) -> usize {
             value += get_point(map, b, a) as usize;
         }
     }
-    value as usize
+    value
 }
 
 fn enhance(ima: &[u8], map: &[Vec<u8>]) -> Vec<Vec<u8>> {
@@ -64,14 +65,15 @@ fn enhance(ima: &[u8], map: &[Vec<u8>]) -> Vec<Vec<u8>> {
 
 fn count(map: &[Vec<u8>]) -> usize {
     let mut n: usize = 0;
-    for y in 0..map.len() {
-        for x in 0..map[0].len() {
-            n += map[y][x] as usize;
+    for row in map {
+        for v in row {
+            n += *v as usize;
         }
     }
     n
 }
 
+#[cfg(test)]
 fn draw(map: &[Vec<u8>]) {
     for row in map {
         for v in row {
@@ -81,6 +83,7 @@ fn draw(map: &[Vec<u8>]) {
     }
 }
 
+#[cfg(test)]
 fn draw_window(map: &[Vec<u8>], x: isize, y: isize) {
     for a in (y - 1)..(y + 2) {
         for b in (x - 1)..(x + 2) {
@@ -123,6 +126,13 @@ fn test() {
 }
 
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let export = match image::read_args(&args) {
+        Ok(None) if !args.is_empty() => panic!("Unknown arguments {:?}", args),
+        Ok(export) => export,
+        Err(e) => panic!("{}", e),
+    };
+
     let mut contents = fs::read_to_string("input/day20.txt").expect("Failed to read file");
     contents.pop();
     let (ima, mut map) = read_map(&contents);
@@ -136,4 +146,10 @@ fn main() {
         map = enhance(&ima, &map);
     }
     println!("Count: {}", count(&map));
+
+    if let Some((path, scale)) = export {
+        Bitmap::from_rows(&map)
+            .save(&path, scale)
+            .expect("Failed to write image");
+    }
 }
@@ -80,10 +80,9 @@ fn play(one_pos: u32, two_pos: u32) -> usize {
     } else {
         player_one.score
     };
-    unsafe {
-        println!("{} {} {}", loser, ROLLS, loser * ROLLS);
-        (loser as usize) * (ROLLS as usize)
-    }
+    let rolls = unsafe { ROLLS };
+    println!("{} {} {}", loser, rolls, loser * rolls);
+    (loser as usize) * (rolls as usize)
 }
 
 #[test]
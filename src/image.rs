@@ -0,0 +1,261 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Black and white image, set pixels are drawn black
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bitmap {
+    width: usize,
+    height: usize,
+    pixels: Vec<bool>,
+}
+
+impl Bitmap {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![false; width * height],
+        }
+    }
+
+    /// Rows of cells where anything but 0 is set
+    pub fn from_rows(rows: &[Vec<u8>]) -> Self {
+        let width = rows.iter().map(|row| row.len()).max().unwrap_or(0);
+        let mut bitmap = Self::new(width, rows.len());
+        for (y, row) in rows.iter().enumerate() {
+            for (x, &cell) in row.iter().enumerate() {
+                if cell != 0 {
+                    bitmap.set(x, y);
+                }
+            }
+        }
+        bitmap
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn set(&mut self, x: usize, y: usize) {
+        self.pixels[y * self.width + x] = true;
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        self.pixels[y * self.width + x]
+    }
+
+    // Rows of the image blown up by `scale`
+    fn scaled_rows(&self, scale: usize) -> impl Iterator<Item = Vec<bool>> + '_ {
+        (0..self.height * scale).map(move |y| {
+            (0..self.width * scale)
+                .map(|x| self.get(x / scale, y / scale))
+                .collect()
+        })
+    }
+
+    /// Binary PBM (P4), each pixel drawn as a `scale` x `scale` square
+    pub fn pbm(&self, scale: usize) -> Vec<u8> {
+        let (width, height) = (self.width * scale, self.height * scale);
+        let mut out = format!("P4\n{} {}\n", width, height).into_bytes();
+        for row in self.scaled_rows(scale) {
+            for byte in row.chunks(8) {
+                let bits = byte
+                    .iter()
+                    .enumerate()
+                    .fold(0u8, |acc, (i, &set)| acc | ((set as u8) << (7 - i)));
+                out.push(bits);
+            }
+        }
+        out
+    }
+
+    /// Binary PPM (P6)
+    pub fn ppm(&self, scale: usize) -> Vec<u8> {
+        let (width, height) = (self.width * scale, self.height * scale);
+        let mut out = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+        for row in self.scaled_rows(scale) {
+            for set in row {
+                out.extend_from_slice(&[if set { 0 } else { 255 }; 3]);
+            }
+        }
+        out
+    }
+
+    /// 8-bit greyscale PNG with the image data stored uncompressed
+    pub fn png(&self, scale: usize) -> Vec<u8> {
+        let (width, height) = (self.width * scale, self.height * scale);
+        let mut raw = Vec::with_capacity((width + 1) * height);
+        for row in self.scaled_rows(scale) {
+            // Filter type None
+            raw.push(0);
+            raw.extend(row.iter().map(|&set| if set { 0 } else { 255 }));
+        }
+
+        let mut header = Vec::new();
+        header.extend_from_slice(&(width as u32).to_be_bytes());
+        header.extend_from_slice(&(height as u32).to_be_bytes());
+        // Bit depth, colour type, compression, filter, interlace
+        header.extend_from_slice(&[8, 0, 0, 0, 0]);
+
+        let mut out = b"\x89PNG\r\n\x1a\n".to_vec();
+        png_chunk(&mut out, b"IHDR", &header);
+        png_chunk(&mut out, b"IDAT", &zlib_stored(&raw));
+        png_chunk(&mut out, b"IEND", &[]);
+        out
+    }
+
+    /// Write the image in the format picked by the file extension: pbm, ppm
+    /// or png
+    pub fn save(&self, path: &Path, scale: usize) -> io::Result<()> {
+        let data = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("pbm") => self.pbm(scale),
+            Some("ppm") => self.ppm(scale),
+            Some("png") => self.png(scale),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("unknown image format for {}", path.display()),
+                ))
+            }
+        };
+        fs::write(path, data)
+    }
+}
+
+/// Number of pixels per cell, 1 when not given
+pub fn read_scale(scale: Option<&String>) -> Result<usize, String> {
+    match scale.map(|scale| scale.parse()) {
+        None => Ok(1),
+        Some(Ok(scale)) if scale > 0 => Ok(scale),
+        _ => Err(format!("Invalid scale '{}'", scale.unwrap())),
+    }
+}
+
+/// Reads `--image <file> [scale]`, None if the arguments ask for something
+/// else
+pub fn read_args(args: &[String]) -> Result<Option<(PathBuf, usize)>, String> {
+    match args {
+        [flag, path, rest @ ..] if flag == "--image" && rest.len() <= 1 => {
+            Ok(Some((PathBuf::from(path), read_scale(rest.first())?)))
+        }
+        _ => Ok(None),
+    }
+}
+
+#[test]
+fn test_read_args() {
+    let args = |s: &str| s.split_whitespace().map(String::from).collect::<Vec<_>>();
+    assert_eq!(read_args(&args("")), Ok(None));
+    assert_eq!(read_args(&args("--frames out")), Ok(None));
+    assert_eq!(
+        read_args(&args("--image out.png")),
+        Ok(Some((PathBuf::from("out.png"), 1)))
+    );
+    assert_eq!(
+        read_args(&args("--image out.pbm 8")),
+        Ok(Some((PathBuf::from("out.pbm"), 8)))
+    );
+    assert!(read_args(&args("--image out.pbm big")).is_err());
+    assert!(read_args(&args("--image out.pbm 0")).is_err());
+}
+
+fn png_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = crc32(&out[start..]);
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+// zlib stream made of stored (uncompressed) deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(0xffff).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        out.push(blocks.peek().is_none() as u8);
+        let len = block.len() as u16;
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[test]
+fn test_checksums() {
+    assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    assert_eq!(crc32(b"IEND"), 0xae42_6082);
+    assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+}
+
+#[test]
+fn test_pbm() {
+    let bitmap = Bitmap::from_rows(&[vec![1, 0, 1], vec![0, 1, 0]]);
+    assert_eq!(bitmap.pbm(1), b"P4\n3 2\n\xa0\x40".to_vec());
+    assert_eq!(
+        bitmap.pbm(3),
+        b"P4\n9 6\n\xe3\x80\xe3\x80\xe3\x80\x1c\x00\x1c\x00\x1c\x00".to_vec()
+    );
+}
+
+#[test]
+fn test_ppm() {
+    let bitmap = Bitmap::from_rows(&[vec![1, 0]]);
+    let mut expected = b"P6\n4 2\n255\n".to_vec();
+    for _ in 0..2 {
+        expected.extend_from_slice(&[0, 0, 0, 0, 0, 0, 255, 255, 255, 255, 255, 255]);
+    }
+    assert_eq!(bitmap.ppm(2), expected);
+}
+
+#[test]
+fn test_png() {
+    let bitmap = Bitmap::from_rows(&[vec![1, 0], vec![0, 1]]);
+    let png = bitmap.png(2);
+    assert!(png.starts_with(b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR"));
+    assert_eq!(&png[16..24], &[0, 0, 0, 4, 0, 0, 0, 4]);
+    assert!(png.ends_with(b"IEND\xae\x42\x60\x82"));
+
+    // Four rows of a filter byte and four pixels in a single stored block
+    let raw = [
+        0, 0, 0, 255, 255, 0, 0, 0, 255, 255, 0, 255, 255, 0, 0, 0, 255, 255, 0, 0,
+    ];
+    let idat = zlib_stored(&raw);
+    assert_eq!(&idat[..7], &[0x78, 0x01, 1, 20, 0, 0xeb, 0xff]);
+    let start = 8 + 25 + 8;
+    assert_eq!(&png[start..start + idat.len()], &idat[..]);
+}
@@ -0,0 +1,207 @@
+use std::fmt;
+
+pub const GLYPH_WIDTH: usize = 4;
+pub const GLYPH_HEIGHT: usize = 6;
+
+// Glyphs are separated by one blank column
+const GLYPH_SPACING: usize = GLYPH_WIDTH + 1;
+
+// The 4x6 letters used by the puzzles, row by row
+const ALPHABET: [(char, &str); 17] = [
+    ('A', ".##.#..##..######..##..#"),
+    ('B', "###.#..####.#..##..####."),
+    ('C', ".##.#..##...#...#..#.##."),
+    ('E', "#####...###.#...#...####"),
+    ('F', "#####...###.#...#...#..."),
+    ('G', ".##.#..##...#.###..#.###"),
+    ('H', "#..##..######..##..##..#"),
+    ('I', ".###..#...#...#...#..###"),
+    ('J', "..##...#...#...##..#.##."),
+    ('K', "#..##.#.##..#.#.#.#.#..#"),
+    ('L', "#...#...#...#...#...####"),
+    ('O', ".##.#..##..##..##..#.##."),
+    ('P', "###.#..##..####.#...#..."),
+    ('R', "###.#..##..####.#.#.#..#"),
+    ('S', ".####...#....##....####."),
+    ('U', "#..##..##..##..##..#.##."),
+    ('Z', "####...#..#..#..#...####"),
+];
+
+#[derive(Debug, PartialEq)]
+pub enum OcrError {
+    // Number of rows left once blank rows around the text are dropped
+    Height(usize),
+    // Column of the first cell of each glyph that matched no letter
+    Unrecognized(Vec<usize>),
+}
+
+impl fmt::Display for OcrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OcrError::Height(rows) => write!(
+                f,
+                "text is {} rows high, letters are {} rows high",
+                rows, GLYPH_HEIGHT
+            ),
+            OcrError::Unrecognized(columns) => {
+                write!(f, "unrecognized glyphs at columns {:?}", columns)
+            }
+        }
+    }
+}
+
+fn glyph(rows: &[&[u8]], column: usize) -> String {
+    let mut cells = String::new();
+    for row in rows {
+        for x in column..column + GLYPH_WIDTH {
+            cells.push(if row.get(x) == Some(&b'#') { '#' } else { '.' });
+        }
+    }
+    cells
+}
+
+// The letters in the glyphs starting at `start` and every glyph spacing after
+// it up to `end`, or the columns of the glyphs that matched no letter
+fn read_glyphs(rows: &[&[u8]], start: usize, end: usize) -> Result<String, Vec<usize>> {
+    let mut text = String::new();
+    let mut unrecognized = Vec::new();
+    for column in (start..end).step_by(GLYPH_SPACING) {
+        let cells = glyph(rows, column);
+        match ALPHABET.iter().find(|(_, letter)| *letter == cells) {
+            Some(&(c, _)) => text.push(c),
+            None => unrecognized.push(column),
+        }
+    }
+
+    if unrecognized.is_empty() {
+        Ok(text)
+    } else {
+        Err(unrecognized)
+    }
+}
+
+/// Read the letters out of a grid of `#` and `.` lines such as the folded
+/// day13 paper. Blank rows and columns around the text are ignored.
+pub fn recognize(grid: &str) -> Result<String, OcrError> {
+    let mut rows: Vec<&[u8]> = grid.lines().map(str::as_bytes).collect();
+    while rows.last().is_some_and(|row| !row.contains(&b'#')) {
+        rows.pop();
+    }
+    let blank = rows.iter().take_while(|row| !row.contains(&b'#')).count();
+    let rows = &rows[blank..];
+    if rows.len() != GLYPH_HEIGHT {
+        return Err(OcrError::Height(rows.len()));
+    }
+
+    let width = rows.iter().map(|row| row.len()).max().unwrap_or(0);
+    let filled = |x: &usize| rows.iter().any(|row| row.get(*x) == Some(&b'#'));
+    let left = (0..width).find(filled).unwrap_or(0);
+    let right = (0..width).rfind(filled).map_or(0, |x| x + 1);
+
+    // I starts with a blank column, so the text may begin one column before
+    // its first dot
+    match read_glyphs(rows, left, right) {
+        Err(unrecognized) if left > 0 => match read_glyphs(rows, left - 1, right) {
+            Ok(text) => Ok(text),
+            Err(_) => Err(OcrError::Unrecognized(unrecognized)),
+        },
+        result => result.map_err(OcrError::Unrecognized),
+    }
+}
+
+/// Draw `text` in the puzzle letters, the inverse of `recognize`. Fails with
+/// the first character that has no glyph.
+pub fn render(text: &str) -> Result<String, char> {
+    let mut glyphs = Vec::new();
+    for c in text.chars() {
+        match ALPHABET.iter().find(|&&(letter, _)| letter == c) {
+            Some((_, cells)) => glyphs.push(cells.as_bytes()),
+            None => return Err(c),
+        }
+    }
+
+    let mut grid = String::new();
+    for y in 0..GLYPH_HEIGHT {
+        for (i, cells) in glyphs.iter().enumerate() {
+            if i > 0 {
+                grid.push('.');
+            }
+            let row = &cells[y * GLYPH_WIDTH..(y + 1) * GLYPH_WIDTH];
+            grid.extend(row.iter().map(|&c| c as char));
+        }
+        grid.push('\n');
+    }
+    Ok(grid)
+}
+
+#[test]
+fn test_render() {
+    assert_eq!(
+        render("LO"),
+        Ok("#.....##.
+#....#..#
+#....#..#
+#....#..#
+#....#..#
+####..##.
+"
+        .to_string())
+    );
+    assert_eq!(
+        recognize(&render("HELLO").unwrap()),
+        Ok("HELLO".to_string())
+    );
+    assert_eq!(render("HEY"), Err('Y'));
+}
+
+#[test]
+fn test_recognize() {
+    let grid = "\
+#..#.###..####.####.
+#..#.#..#.#....#....
+####.###..###..###..
+#..#.#..#.#....#....
+#..#.#..#.#....#....
+#..#.###..####.#....
+....................
+";
+    assert_eq!(recognize(grid), Ok("HBEF".to_string()));
+
+    // Trailing spacer column trimmed, as in the puzzle output
+    let grid = ".##..###.
+#..#.#..#
+#....#..#
+#....###.
+#..#.#...
+.##..#...";
+    assert_eq!(recognize(grid), Ok("CP".to_string()));
+
+    // Paper wider than the text on either side
+    for (text, before, after) in [("HI", 0, 5), ("HI", 3, 0), ("IH", 2, 7)] {
+        let grid: String = render(text)
+            .unwrap()
+            .lines()
+            .map(|line| format!("{}{}{}\n", ".".repeat(before), line, ".".repeat(after)))
+            .collect();
+        assert_eq!(recognize(&grid), Ok(text.to_string()));
+    }
+}
+
+#[test]
+fn test_recognize_errors() {
+    let grid = "#####
+#...#
+#...#
+#...#
+#####";
+    assert_eq!(recognize(grid), Err(OcrError::Height(5)));
+
+    let grid = "\
+#..#.####.#..#.
+#..#.#..#.#..#.
+####.#..#.####.
+#..#.#..#.#..#.
+#..#.#..#.#..#.
+#..#.####.#..#.";
+    assert_eq!(recognize(grid), Err(OcrError::Unrecognized(vec![5])));
+}